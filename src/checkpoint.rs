@@ -0,0 +1,182 @@
+use sqlx::{PgConnection, Pool, Postgres};
+use crate::AppError;
+use crate::tables::GeoTables;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::io::Read;
+
+// Records the progress of an import, so that an interrupted import can be
+// resumed (using --resume) rather than started again. Each batch is given a
+// number, and is stored in the same transaction as a row in
+// geo.import_checkpoints with its number, the byte offset in the (unzipped)
+// source just after its last record, the geoname id of the next record, and
+// the SHA-256 of the source data before that offset. An import is only
+// resumed if the data it has already imported is unchanged, but a malformed
+// line after the last checkpoint can be corrected before resuming.
+// The table is in the staging schema while the import is running.
+// Batches are cut in the same places on every run over the same file, so a
+// resumed import produces exactly the batches that a clean run would have.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub batch_no: i32,
+    pub end_offset: i64,
+    pub next_geoname_id: Option<i64>,
+    pub prefix_sha256: Option<String>,
+}
+
+// Batches are stored by more than one writer, so they are not necessarily
+// completed in order. An import resumes from the end of the last batch of
+// the unbroken run of completed batches from the start of the file, and
+// skips any later batches that were also completed.
+
+#[derive(Debug)]
+pub struct ResumePoint {
+    pub last_contiguous: Option<Checkpoint>,
+    pub completed: HashSet<i32>,
+}
+
+impl ResumePoint {
+    pub fn new() -> Self {
+        ResumePoint {
+            last_contiguous: None,
+            completed: HashSet::new(),
+        }
+    }
+
+    pub fn from_checkpoints(checkpoints: Vec<Checkpoint>) -> Self {
+        let completed: HashSet<i32> = checkpoints.iter().map(|c| c.batch_no).collect();
+        let mut last_contiguous = None;
+        for cp in checkpoints {     // in batch_no order
+            let expected = last_contiguous.as_ref().map_or(1, |c: &Checkpoint| c.batch_no + 1);
+            if cp.batch_no != expected {
+                break;
+            }
+            last_contiguous = Some(cp);
+        }
+        ResumePoint { last_contiguous, completed }
+    }
+
+    pub fn is_completed(&self, batch_no: i32) -> bool {
+        self.completed.contains(&batch_no)
+    }
+}
+
+impl Default for ResumePoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+pub async fn fetch_resume_point(pool: &Pool<Postgres>, tables: &GeoTables, source_file_name: &str) -> Result<ResumePoint, AppError> {
+
+    let sql = &format!("select to_regclass('{}') is not null", tables.name("import_checkpoints"));
+    let has_table: bool = sqlx::query_scalar(sql).fetch_one(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    if !has_table {
+        return Err(AppError::PipelineError("There is no checkpoint table in the staging schema, so no import to resume".to_string()));
+    }
+
+    let sql = &format!("select distinct source_file from {} where source_file <> $1", tables.name("import_checkpoints"));
+    let other_files: Vec<String> = sqlx::query_scalar(sql).bind(source_file_name).fetch_all(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    if let Some(other) = other_files.first() {
+        return Err(AppError::PipelineError(format!("The import to be resumed was from {}, not {}", other, source_file_name)));
+    }
+
+    let sql = &format!(r#"select batch_no, end_offset, next_geoname_id, prefix_sha256 from {}
+                 order by batch_no"#, tables.name("import_checkpoints"));
+    let rows: Vec<(i32, i64, Option<i64>, Option<String>)> = sqlx::query_as(sql).fetch_all(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+
+    let checkpoints = rows.into_iter()
+                .map(|(batch_no, end_offset, next_geoname_id, prefix_sha256)| Checkpoint { batch_no, end_offset, next_geoname_id, prefix_sha256 })
+                .collect();
+    Ok(ResumePoint::from_checkpoints(checkpoints))
+}
+
+
+pub async fn store_checkpoint(cp: &Checkpoint, source_file_name: &str, tables: &GeoTables, conn: &mut PgConnection) -> Result<(), AppError> {
+
+    let sql = &format!(r#"insert into {} (batch_no, source_file, end_offset, next_geoname_id, prefix_sha256)
+                 values ($1, $2, $3, $4, $5)"#, tables.name("import_checkpoints"));
+    sqlx::query(sql)
+        .bind(cp.batch_no).bind(source_file_name).bind(cp.end_offset).bind(cp.next_geoname_id).bind(&cp.prefix_sha256)
+        .execute(conn).await
+        .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    Ok(())
+}
+
+
+// Hashes the source data from its start, reading it separately from the
+// import, and keeping up with it, so that the hash of the data before each
+// checkpoint's offset is found without the data being read again.
+
+pub struct PrefixHasher {
+    reader: Box<dyn Read + Send>,
+    hasher: Sha256,
+    position: u64,
+}
+
+impl PrefixHasher {
+    pub fn new(reader: Box<dyn Read + Send>) -> Self {
+        PrefixHasher {
+            reader,
+            hasher: Sha256::new(),
+            position: 0,
+        }
+    }
+
+    // Offsets must not decrease. If the data ends before the offset the hash
+    // is of all the data, so will not match one taken over the full length.
+
+    pub fn hash_to(&mut self, offset: u64) -> std::io::Result<String> {
+        if offset < self.position {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput,
+                        format!("data already hashed up to byte {}, so cannot be hashed to byte {}", self.position, offset)));
+        }
+        self.position += std::io::copy(&mut self.reader.by_ref().take(offset - self.position), &mut self.hasher)?;
+        Ok(format!("{:x}", self.hasher.clone().finalize()))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cp(batch_no: i32) -> Checkpoint {
+        Checkpoint { batch_no, end_offset: batch_no as i64 * 1000, next_geoname_id: Some(batch_no as i64), prefix_sha256: None }
+    }
+
+    #[test]
+    fn check_resume_from_last_contiguous_batch() {
+        let rp = ResumePoint::from_checkpoints(vec![cp(1), cp(2), cp(3), cp(5)]);
+        assert_eq!(rp.last_contiguous, Some(cp(3)));
+        assert!(rp.is_completed(5));
+        assert!(!rp.is_completed(4));
+    }
+
+    #[test]
+    fn check_prefix_hashes_match_data_before_offset() {
+        let data = b"1\t100\ten\tLondon\n2\t100\tfr\tLondres\n3\t200\tde\tParis\n".to_vec();
+        let expected = |n: usize| format!("{:x}", Sha256::digest(&data[..n]));
+        let mut hasher = PrefixHasher::new(Box::new(std::io::Cursor::new(data.clone())));
+        assert_eq!(hasher.hash_to(0).unwrap(), expected(0));
+        assert_eq!(hasher.hash_to(15).unwrap(), expected(15));
+        assert_eq!(hasher.hash_to(15).unwrap(), expected(15));
+        assert_eq!(hasher.hash_to(32).unwrap(), expected(32));
+        assert!(hasher.hash_to(10).is_err());
+        assert_eq!(hasher.hash_to(1000).unwrap(), expected(data.len()));
+    }
+
+    #[test]
+    fn check_resume_from_start_if_first_batch_missing() {
+        let rp = ResumePoint::from_checkpoints(vec![cp(2), cp(3)]);
+        assert_eq!(rp.last_contiguous, None);
+        assert!(rp.is_completed(2));
+        let rp = ResumePoint::from_checkpoints(Vec::new());
+        assert_eq!(rp.last_contiguous, None);
+    }
+}
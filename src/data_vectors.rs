@@ -1,43 +1,846 @@
-use crate::import::AltRec;
+use crate::import::{AltName, AltRec};
 use crate::AppError;
-use sqlx::{postgres::PgQueryResult, Pool, Postgres};
+use crate::periods;
+use crate::links;
+use crate::tables::GeoTables;
+use chrono::NaiveDate;
+use sqlx::PgConnection;
+use std::collections::HashMap;
+
+// The two ways of moving a batch of records into the database. COPY is the
+// default. UNNEST is the original approach, retained for comparison.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadMode {
+    Copy,
+    Unnest,
+}
+
+// Holds a batch of aggregated alt_names rows. Source records with the same
+// geo_id, name and historic period (the historic flag together with the raw
+// from and to values) are combined as they are added, with their
+// lang codes collected into a sorted list without duplicates (stored as a
+// text[]), so each entry is a final geo.alt_names row. Records without a
+// language code ('none') add nothing to the list, so a name that only has
+// such records has an empty list, and "none" never appears with real codes.
+// The short name and colloquial flags are set if they are set on any of the
+// combined records. Whether a name is preferred depends on the language, so
+// as well as the is_preferred flag (set if the name is preferred in any
+// language) the languages in which it is preferred are listed separately,
+// following the same rules.
+// The alternate_name_ids of all the combined source records are kept, so
+// that each row can be traced back to the Geonames records it came from.
 
 pub struct AltRecVecs {
     pub geo_ids: Vec<i64>,
     pub names: Vec<String>,
-    pub langs: Vec<String>,
-    pub historics: Vec<String>,
+    pub langs: Vec<Vec<String>>,
+    pub historics: Vec<bool>,
+    pub valid_froms: Vec<Option<NaiveDate>>,
+    pub valid_tos: Vec<Option<NaiveDate>>,
+    pub froms_raw: Vec<Option<String>>,
+    pub tos_raw: Vec<Option<String>>,
+    pub scripts: Vec<String>,
+    pub preferreds: Vec<bool>,
+    pub preferred_langs: Vec<Vec<String>>,
+    pub shorts: Vec<bool>,
+    pub colloquials: Vec<bool>,
+    pub alt_name_ids: Vec<Vec<i64>>,
+    index: HashMap<AltNameKey, usize>,
 }
 
+type AltNameKey = (i64, String, bool, Option<String>, Option<String>);
+
 
 impl AltRecVecs{
     pub fn new(vsize: usize) -> Self {
-        AltRecVecs { 
+        AltRecVecs {
             geo_ids: Vec::with_capacity(vsize),
             names: Vec::with_capacity(vsize),
             langs: Vec::with_capacity(vsize),
             historics: Vec::with_capacity(vsize),
+            valid_froms: Vec::with_capacity(vsize),
+            valid_tos: Vec::with_capacity(vsize),
+            froms_raw: Vec::with_capacity(vsize),
+            tos_raw: Vec::with_capacity(vsize),
+            scripts: Vec::with_capacity(vsize),
+            preferreds: Vec::with_capacity(vsize),
+            preferred_langs: Vec::with_capacity(vsize),
+            shorts: Vec::with_capacity(vsize),
+            colloquials: Vec::with_capacity(vsize),
+            alt_name_ids: Vec::with_capacity(vsize),
+            index: HashMap::with_capacity(vsize),
         }
     }
 
-    pub fn add_data(&mut self, r: &AltRec) 
+    pub fn add_data(&mut self, r: &AltRec)
     {
-        self.geo_ids.push(r.geo_id);
-        self.names.push(r.name.clone());
-        self.langs.push(r.lang.clone());
-        self.historics.push(r.historic.clone());
+        let key = (r.geo_id, r.name.clone(), r.is_historic, r.from_raw.clone(), r.to_raw.clone());
+        match self.index.get(&key) {
+            Some(&i) => {
+                add_lang(&mut self.langs[i], &r.lang);
+                if r.is_preferred {
+                    add_lang(&mut self.preferred_langs[i], &r.lang);
+                    self.preferreds[i] = true;
+                }
+                self.shorts[i] |= r.is_short;
+                self.colloquials[i] |= r.is_colloquial;
+                self.alt_name_ids[i].push(r.alt_name_id);
+            },
+            None => {
+                self.index.insert(key, self.geo_ids.len());
+                self.geo_ids.push(r.geo_id);
+                self.names.push(r.name.clone());
+                let mut langs = Vec::new();
+                add_lang(&mut langs, &r.lang);
+                self.historics.push(r.is_historic);
+                self.valid_froms.push(r.valid_from);
+                self.valid_tos.push(r.valid_to);
+                self.froms_raw.push(r.from_raw.clone());
+                self.tos_raw.push(r.to_raw.clone());
+                self.scripts.push(r.script.clone());
+                self.preferreds.push(r.is_preferred);
+                self.preferred_langs.push(if r.is_preferred { langs.clone() } else { Vec::new() });
+                self.langs.push(langs);
+                self.shorts.push(r.is_short);
+                self.colloquials.push(r.is_colloquial);
+                self.alt_name_ids.push(vec![r.alt_name_id]);
+            },
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.geo_ids.is_empty()
     }
 
 
-    pub async fn store_data(&self, pool : &Pool<Postgres>) -> Result<PgQueryResult, AppError> {
+    pub async fn store_data(&self, conn: &mut PgConnection, tables: &GeoTables, mode: LoadMode) -> Result<u64, AppError> {
+
+        if self.is_empty() {
+            return Ok(0);
+        }
+
+        match mode {
+            LoadMode::Copy => self.copy_data(conn, tables).await,
+            LoadMode::Unnest => self.unnest_data(conn, tables).await,
+        }
+    }
+
 
-        let sql = r#"INSERT INTO geo.alt_src_names (geo_id, alt_name, lang, historic) 
-            SELECT * FROM UNNEST($1::int[], $2::text[], $3::text[], $4::text[])"#;
+    async fn copy_data(&self, conn: &mut PgConnection, tables: &GeoTables) -> Result<u64, AppError> {
+
+        let sql = &format!(r#"COPY {} (id, alt_name, langs, is_historic, valid_from, valid_to, from_raw, to_raw, script,
+                    is_preferred_name, preferred_langs, is_short_name, is_colloquial, alternate_name_ids) FROM STDIN"#, tables.name("alt_names"));
+
+        let mut buf = String::with_capacity(self.geo_ids.len() * 48);
+        for i in 0..self.geo_ids.len() {
+            buf.push_str(&self.geo_ids[i].to_string());
+            buf.push('\t');
+            push_copy_text(&mut buf, &self.names[i]);
+            buf.push('\t');
+            push_copy_text(&mut buf, &to_text_array_literal(&self.langs[i]));
+            buf.push('\t');
+            push_copy_bool(&mut buf, self.historics[i]);
+            buf.push('\t');
+            push_copy_date(&mut buf, &self.valid_froms[i]);
+            buf.push('\t');
+            push_copy_date(&mut buf, &self.valid_tos[i]);
+            buf.push('\t');
+            push_copy_opt_text(&mut buf, &self.froms_raw[i]);
+            buf.push('\t');
+            push_copy_opt_text(&mut buf, &self.tos_raw[i]);
+            buf.push('\t');
+            push_copy_text(&mut buf, &self.scripts[i]);
+            buf.push('\t');
+            push_copy_bool(&mut buf, self.preferreds[i]);
+            buf.push('\t');
+            push_copy_text(&mut buf, &to_text_array_literal(&self.preferred_langs[i]));
+            buf.push('\t');
+            push_copy_bool(&mut buf, self.shorts[i]);
+            buf.push('\t');
+            push_copy_bool(&mut buf, self.colloquials[i]);
+            buf.push('\t');
+            buf.push_str(&to_int_array_literal(&self.alt_name_ids[i]));
+            buf.push('\n');
+        }
+
+        copy_text_rows(conn, sql, buf).await
+    }
+
+
+    async fn unnest_data(&self, conn: &mut PgConnection, tables: &GeoTables) -> Result<u64, AppError> {
+
+        // UNNEST would flatten a two dimensional array, and Postgres arrays
+        // must be rectangular anyway, so the lists of langs and of
+        // alternate_name_ids are passed as array literals and cast back to
+        // arrays on insertion.
+
+        let sql = &format!(r#"INSERT INTO {} (id, alt_name, langs, is_historic, valid_from, valid_to, from_raw, to_raw, script,
+                    is_preferred_name, preferred_langs, is_short_name, is_colloquial, alternate_name_ids)
+            SELECT id, alt_name, langs::text[], is_historic, valid_from, valid_to, from_raw, to_raw, script,
+                    is_preferred_name, preferred_langs::text[], is_short_name, is_colloquial, alternate_name_ids::int8[]
+            FROM UNNEST($1::int[], $2::text[], $3::text[], $4::bool[], $5::date[], $6::date[], $7::text[], $8::text[], $9::text[],
+                    $10::bool[], $11::text[], $12::bool[], $13::bool[], $14::text[])
+                AS u(id, alt_name, langs, is_historic, valid_from, valid_to, from_raw, to_raw, script,
+                    is_preferred_name, preferred_langs, is_short_name, is_colloquial, alternate_name_ids)"#, tables.name("alt_names"));
+
+        let lang_lists: Vec<String> = self.langs.iter().map(|langs| to_text_array_literal(langs)).collect();
+        let preferred_lists: Vec<String> = self.preferred_langs.iter().map(|langs| to_text_array_literal(langs)).collect();
+        let id_lists: Vec<String> = self.alt_name_ids.iter().map(|ids| to_int_array_literal(ids)).collect();
 
         sqlx::query(sql)
-        .bind(&self.geo_ids).bind(&self.names).bind(&self.langs).bind(&self.historics)
-        .execute(pool).await
+        .bind(&self.geo_ids).bind(&self.names).bind(&lang_lists).bind(&self.historics)
+        .bind(&self.valid_froms).bind(&self.valid_tos).bind(&self.froms_raw).bind(&self.tos_raw)
+        .bind(&self.scripts).bind(&self.preferreds).bind(&preferred_lists)
+        .bind(&self.shorts).bind(&self.colloquials).bind(&id_lists)
+        .execute(conn).await
+        .map(|r| r.rows_affected())
+        .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+    }
+}
+
+
+// Holds the geonames to Wikidata links, taken from the records with the
+// 'wkdt' language code, where the 'name' is the Wikidata QID.
+
+pub struct WikidataVecs {
+    pub geo_ids: Vec<i64>,
+    pub qids: Vec<String>,
+    pub alt_name_ids: Vec<i64>,
+}
+
+impl WikidataVecs {
+    pub fn new(vsize: usize) -> Self {
+        WikidataVecs {
+            geo_ids: Vec::with_capacity(vsize),
+            qids: Vec::with_capacity(vsize),
+            alt_name_ids: Vec::with_capacity(vsize),
+        }
+    }
+
+    pub fn add_data(&mut self, geo_id: i64, qid: &str, alt_name_id: i64) {
+        self.geo_ids.push(geo_id);
+        self.qids.push(qid.trim().to_string());
+        self.alt_name_ids.push(alt_name_id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.geo_ids.is_empty()
+    }
+
+    pub async fn store_data(&self, conn: &mut PgConnection, tables: &GeoTables, mode: LoadMode) -> Result<u64, AppError> {
+
+        if self.is_empty() {
+            return Ok(0);
+        }
+
+        match mode {
+            LoadMode::Copy => {
+                let sql = &format!(r#"COPY {} (geoname_id, qid, alternate_name_id) FROM STDIN"#, tables.name("wikidata_xref"));
+                let mut buf = String::with_capacity(self.geo_ids.len() * 32);
+                for i in 0..self.geo_ids.len() {
+                    buf.push_str(&self.geo_ids[i].to_string());
+                    buf.push('\t');
+                    push_copy_text(&mut buf, &self.qids[i]);
+                    buf.push('\t');
+                    buf.push_str(&self.alt_name_ids[i].to_string());
+                    buf.push('\n');
+                }
+                copy_text_rows(conn, sql, buf).await
+            },
+            LoadMode::Unnest => {
+                let sql = &format!(r#"INSERT INTO {} (geoname_id, qid, alternate_name_id)
+                    SELECT * FROM UNNEST($1::int[], $2::text[], $3::int8[])"#, tables.name("wikidata_xref"));
+                sqlx::query(sql)
+                .bind(&self.geo_ids).bind(&self.qids).bind(&self.alt_name_ids)
+                .execute(conn).await
+                .map(|r| r.rows_affected())
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+            },
+        }
+    }
+}
+
+
+// Holds the URLs from the records with the 'link' language code, with the
+// host and (for Wikipedia articles) the language edition extracted.
+
+pub struct LinkVecs {
+    pub geo_ids: Vec<i64>,
+    pub urls: Vec<String>,
+    pub hosts: Vec<Option<String>>,
+    pub wiki_langs: Vec<Option<String>>,
+    pub alt_name_ids: Vec<i64>,
+}
+
+impl LinkVecs {
+    pub fn new(vsize: usize) -> Self {
+        LinkVecs {
+            geo_ids: Vec::with_capacity(vsize),
+            urls: Vec::with_capacity(vsize),
+            hosts: Vec::with_capacity(vsize),
+            wiki_langs: Vec::with_capacity(vsize),
+            alt_name_ids: Vec::with_capacity(vsize),
+        }
+    }
+
+    pub fn add_data(&mut self, geo_id: i64, url: &str, alt_name_id: i64) {
+        let parts = links::parse_link(url);
+        self.geo_ids.push(geo_id);
+        self.urls.push(url.trim().to_string());
+        self.hosts.push(parts.host);
+        self.wiki_langs.push(parts.wiki_lang);
+        self.alt_name_ids.push(alt_name_id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.geo_ids.is_empty()
+    }
+
+    pub async fn store_data(&self, conn: &mut PgConnection, tables: &GeoTables, mode: LoadMode) -> Result<u64, AppError> {
+
+        if self.is_empty() {
+            return Ok(0);
+        }
+
+        match mode {
+            LoadMode::Copy => {
+                let sql = &format!(r#"COPY {} (geoname_id, url, host, wiki_lang, alternate_name_id) FROM STDIN"#, tables.name("links"));
+                let mut buf = String::with_capacity(self.geo_ids.len() * 80);
+                for i in 0..self.geo_ids.len() {
+                    buf.push_str(&self.geo_ids[i].to_string());
+                    buf.push('\t');
+                    push_copy_text(&mut buf, &self.urls[i]);
+                    buf.push('\t');
+                    push_copy_opt_text(&mut buf, &self.hosts[i]);
+                    buf.push('\t');
+                    push_copy_opt_text(&mut buf, &self.wiki_langs[i]);
+                    buf.push('\t');
+                    buf.push_str(&self.alt_name_ids[i].to_string());
+                    buf.push('\n');
+                }
+                copy_text_rows(conn, sql, buf).await
+            },
+            LoadMode::Unnest => {
+                let sql = &format!(r#"INSERT INTO {} (geoname_id, url, host, wiki_lang, alternate_name_id)
+                    SELECT * FROM UNNEST($1::int[], $2::text[], $3::text[], $4::text[], $5::int8[])"#, tables.name("links"));
+                sqlx::query(sql)
+                .bind(&self.geo_ids).bind(&self.urls).bind(&self.hosts).bind(&self.wiki_langs)
+                .bind(&self.alt_name_ids)
+                .execute(conn).await
+                .map(|r| r.rows_affected())
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+            },
+        }
+    }
+}
+
+
+// Holds the transport codes, from the records with the 'iata', 'icao',
+// 'faac' (FAA) and 'unlc' (UN/LOCODE) language codes. The language code
+// becomes the code type, and the codes themselves are stored in upper case.
+
+pub struct CodeVecs {
+    pub geo_ids: Vec<i64>,
+    pub code_types: Vec<String>,
+    pub codes: Vec<String>,
+    pub alt_name_ids: Vec<i64>,
+}
+
+impl CodeVecs {
+    pub fn new(vsize: usize) -> Self {
+        CodeVecs {
+            geo_ids: Vec::with_capacity(vsize),
+            code_types: Vec::with_capacity(vsize),
+            codes: Vec::with_capacity(vsize),
+            alt_name_ids: Vec::with_capacity(vsize),
+        }
+    }
+
+    pub fn add_data(&mut self, geo_id: i64, code_type: &str, code: &str, alt_name_id: i64) {
+        self.geo_ids.push(geo_id);
+        self.code_types.push(code_type.to_string());
+        self.codes.push(code.trim().to_uppercase());
+        self.alt_name_ids.push(alt_name_id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.geo_ids.is_empty()
+    }
+
+    pub async fn store_data(&self, conn: &mut PgConnection, tables: &GeoTables, mode: LoadMode) -> Result<u64, AppError> {
+
+        if self.is_empty() {
+            return Ok(0);
+        }
+
+        match mode {
+            LoadMode::Copy => {
+                let sql = &format!(r#"COPY {} (geoname_id, code_type, code, alternate_name_id) FROM STDIN"#, tables.name("codes"));
+                let mut buf = String::with_capacity(self.geo_ids.len() * 32);
+                for i in 0..self.geo_ids.len() {
+                    buf.push_str(&self.geo_ids[i].to_string());
+                    buf.push('\t');
+                    push_copy_text(&mut buf, &self.code_types[i]);
+                    buf.push('\t');
+                    push_copy_text(&mut buf, &self.codes[i]);
+                    buf.push('\t');
+                    buf.push_str(&self.alt_name_ids[i].to_string());
+                    buf.push('\n');
+                }
+                copy_text_rows(conn, sql, buf).await
+            },
+            LoadMode::Unnest => {
+                let sql = &format!(r#"INSERT INTO {} (geoname_id, code_type, code, alternate_name_id)
+                    SELECT * FROM UNNEST($1::int[], $2::text[], $3::text[], $4::int8[])"#, tables.name("codes"));
+                sqlx::query(sql)
+                .bind(&self.geo_ids).bind(&self.code_types).bind(&self.codes).bind(&self.alt_name_ids)
+                .execute(conn).await
+                .map(|r| r.rows_affected())
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+            },
+        }
+    }
+}
+
+
+// Holds the postal codes, from the records with the 'post' language code.
+// The normalised form of each code, with spaces removed and in upper case,
+// is stored alongside the original, to simplify matching against addresses.
+
+pub struct PostalVecs {
+    pub geo_ids: Vec<i64>,
+    pub postal_codes: Vec<String>,
+    pub normalised_codes: Vec<String>,
+    pub alt_name_ids: Vec<i64>,
+}
+
+impl PostalVecs {
+    pub fn new(vsize: usize) -> Self {
+        PostalVecs {
+            geo_ids: Vec::with_capacity(vsize),
+            postal_codes: Vec::with_capacity(vsize),
+            normalised_codes: Vec::with_capacity(vsize),
+            alt_name_ids: Vec::with_capacity(vsize),
+        }
+    }
+
+    pub fn add_data(&mut self, geo_id: i64, postal_code: &str, alt_name_id: i64) {
+        self.geo_ids.push(geo_id);
+        self.postal_codes.push(postal_code.to_string());
+        self.normalised_codes.push(normalise_postal_code(postal_code));
+        self.alt_name_ids.push(alt_name_id);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.geo_ids.is_empty()
+    }
+
+    pub async fn store_data(&self, conn: &mut PgConnection, tables: &GeoTables, mode: LoadMode) -> Result<u64, AppError> {
+
+        if self.is_empty() {
+            return Ok(0);
+        }
+
+        match mode {
+            LoadMode::Copy => {
+                let sql = &format!(r#"COPY {} (geoname_id, postal_code, normalised_code, alternate_name_id) FROM STDIN"#, tables.name("postal_codes"));
+                let mut buf = String::with_capacity(self.geo_ids.len() * 40);
+                for i in 0..self.geo_ids.len() {
+                    buf.push_str(&self.geo_ids[i].to_string());
+                    buf.push('\t');
+                    push_copy_text(&mut buf, &self.postal_codes[i]);
+                    buf.push('\t');
+                    push_copy_text(&mut buf, &self.normalised_codes[i]);
+                    buf.push('\t');
+                    buf.push_str(&self.alt_name_ids[i].to_string());
+                    buf.push('\n');
+                }
+                copy_text_rows(conn, sql, buf).await
+            },
+            LoadMode::Unnest => {
+                let sql = &format!(r#"INSERT INTO {} (geoname_id, postal_code, normalised_code, alternate_name_id)
+                    SELECT * FROM UNNEST($1::int[], $2::text[], $3::text[], $4::int8[])"#, tables.name("postal_codes"));
+                sqlx::query(sql)
+                .bind(&self.geo_ids).bind(&self.postal_codes).bind(&self.normalised_codes).bind(&self.alt_name_ids)
+                .execute(conn).await
+                .map(|r| r.rows_affected())
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+            },
+        }
+    }
+}
+
+
+fn normalise_postal_code(postal_code: &str) -> String {
+    postal_code.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_uppercase()
+}
+
+
+// Holds the source records as read, before any filtering or aggregation,
+// for geo.alt_src_names. Keeping these allows the names of a geoname to be
+// re-aggregated when a delta file changes some of its records.
+
+pub struct SrcVecs {
+    pub alt_name_ids: Vec<i64>,
+    pub geo_ids: Vec<i64>,
+    pub langs: Vec<Option<String>>,
+    pub names: Vec<String>,
+    pub preferreds: Vec<bool>,
+    pub shorts: Vec<bool>,
+    pub colloquials: Vec<bool>,
+    pub historics: Vec<bool>,
+    pub froms_raw: Vec<Option<String>>,
+    pub tos_raw: Vec<Option<String>>,
+}
+
+impl SrcVecs {
+    pub fn new(vsize: usize) -> Self {
+        SrcVecs {
+            alt_name_ids: Vec::with_capacity(vsize),
+            geo_ids: Vec::with_capacity(vsize),
+            langs: Vec::with_capacity(vsize),
+            names: Vec::with_capacity(vsize),
+            preferreds: Vec::with_capacity(vsize),
+            shorts: Vec::with_capacity(vsize),
+            colloquials: Vec::with_capacity(vsize),
+            historics: Vec::with_capacity(vsize),
+            froms_raw: Vec::with_capacity(vsize),
+            tos_raw: Vec::with_capacity(vsize),
+        }
+    }
+
+    pub fn add_data(&mut self, s: &AltName) {
+        self.alt_name_ids.push(s.alternate_name_id);
+        self.geo_ids.push(s.geoname_id);
+        self.langs.push(s.iso_language.clone());
+        self.names.push(s.alternate_name.clone());
+        self.preferreds.push(s.is_preferred_name == Some(1));
+        self.shorts.push(s.is_short_name == Some(1));
+        self.colloquials.push(s.is_colloquial == Some(1));
+        self.historics.push(s.is_historic == Some(1));
+        self.froms_raw.push(s.yfrom.clone());
+        self.tos_raw.push(s.yto.clone());
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.alt_name_ids.is_empty()
+    }
+
+    pub async fn store_data(&self, conn: &mut PgConnection, tables: &GeoTables, mode: LoadMode) -> Result<u64, AppError> {
+
+        if self.is_empty() {
+            return Ok(0);
+        }
+
+        match mode {
+            LoadMode::Copy => {
+                let sql = &format!(r#"COPY {} (alternate_name_id, geoname_id, iso_language, alternate_name,
+                    is_preferred_name, is_short_name, is_colloquial, is_historic, yfrom, yto) FROM STDIN"#, tables.name("alt_src_names"));
+                let mut buf = String::with_capacity(self.alt_name_ids.len() * 48);
+                for i in 0..self.alt_name_ids.len() {
+                    buf.push_str(&self.alt_name_ids[i].to_string());
+                    buf.push('\t');
+                    buf.push_str(&self.geo_ids[i].to_string());
+                    buf.push('\t');
+                    push_copy_opt_text(&mut buf, &self.langs[i]);
+                    buf.push('\t');
+                    push_copy_text(&mut buf, &self.names[i]);
+                    buf.push('\t');
+                    push_copy_bool(&mut buf, self.preferreds[i]);
+                    buf.push('\t');
+                    push_copy_bool(&mut buf, self.shorts[i]);
+                    buf.push('\t');
+                    push_copy_bool(&mut buf, self.colloquials[i]);
+                    buf.push('\t');
+                    push_copy_bool(&mut buf, self.historics[i]);
+                    buf.push('\t');
+                    push_copy_opt_text(&mut buf, &self.froms_raw[i]);
+                    buf.push('\t');
+                    push_copy_opt_text(&mut buf, &self.tos_raw[i]);
+                    buf.push('\n');
+                }
+                copy_text_rows(conn, sql, buf).await
+            },
+            LoadMode::Unnest => {
+                let sql = &format!(r#"INSERT INTO {} (alternate_name_id, geoname_id, iso_language, alternate_name,
+                    is_preferred_name, is_short_name, is_colloquial, is_historic, yfrom, yto)
+                    SELECT * FROM UNNEST($1::int8[], $2::int[], $3::text[], $4::text[], $5::bool[], $6::bool[],
+                    $7::bool[], $8::bool[], $9::text[], $10::text[])"#, tables.name("alt_src_names"));
+                sqlx::query(sql)
+                .bind(&self.alt_name_ids).bind(&self.geo_ids).bind(&self.langs).bind(&self.names)
+                .bind(&self.preferreds).bind(&self.shorts).bind(&self.colloquials).bind(&self.historics)
+                .bind(&self.froms_raw).bind(&self.tos_raw)
+                .execute(conn).await
+                .map(|r| r.rows_affected())
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+            },
+        }
+    }
+}
+
+
+// A batch of data, as passed from the source file reader to the database
+// writers. It holds the aggregated alt_names rows and the rows for the
+// other tables populated from the same records.
+
+pub struct ImportBatch {
+    pub alt_names: AltRecVecs,
+    pub wikidata: WikidataVecs,
+    pub links: LinkVecs,
+    pub codes: CodeVecs,
+    pub postal_codes: PostalVecs,
+    pub sources: SrcVecs,
+}
+
+impl ImportBatch {
+    pub fn new(vsize: usize) -> Self {
+        ImportBatch {
+            alt_names: AltRecVecs::new(vsize),
+            wikidata: WikidataVecs::new(vsize / 4),
+            links: LinkVecs::new(vsize / 4),
+            codes: CodeVecs::new(vsize / 10),
+            postal_codes: PostalVecs::new(vsize / 4),
+            sources: SrcVecs::new(vsize * 4),
+        }
+    }
+
+    // All the parts of the batch are written using the same connection,
+    // which may be within a transaction (as when a delta is applied).
+
+    pub async fn store_data(&self, conn: &mut PgConnection, tables: &GeoTables, mode: LoadMode) -> Result<(), AppError> {
+
+        self.alt_names.store_data(conn, tables, mode).await?;
+        self.wikidata.store_data(conn, tables, mode).await?;
+        self.links.store_data(conn, tables, mode).await?;
+        self.codes.store_data(conn, tables, mode).await?;
+        self.postal_codes.store_data(conn, tables, mode).await?;
+        self.sources.store_data(conn, tables, mode).await?;
+        Ok(())
+    }
+}
+
+
+// Sends a buffer of rows, already in Postgres COPY text format, to the database
+// as a single COPY FROM STDIN operation. If sending fails the copy is aborted,
+// so that the pooled connection is left usable.
+
+async fn copy_text_rows(conn: &mut PgConnection, sql: &str, buf: String) -> Result<u64, AppError> {
+
+    let mut copy_in = conn.copy_in_raw(sql).await
+                    .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+
+    if let Err(e) = copy_in.send(buf.into_bytes()).await {
+        let _ = copy_in.abort("Error when sending data").await;
+        return Err(AppError::SqlxError(e, sql.to_string()));
+    }
+
+    copy_in.finish().await
         .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+}
+
+
+fn push_copy_bool(buf: &mut String, b: bool) {
+    buf.push(if b { 't' } else { 'f' });
+}
+
+// Missing values are sent as \N, the COPY text format null marker.
+
+fn push_copy_opt_text(buf: &mut String, s: &Option<String>) {
+    match s {
+        Some(s) => push_copy_text(buf, s),
+        None => buf.push_str("\\N"),
+    }
+}
+
+fn push_copy_date(buf: &mut String, d: &Option<NaiveDate>) {
+    match d {
+        Some(d) => buf.push_str(&periods::to_pg_date_string(d)),
+        None => buf.push_str("\\N"),
+    }
+}
+
+
+// Adds a language code to a sorted list, unless it is already present or is
+// 'none' (the code given to records without a language).
+
+fn add_lang(langs: &mut Vec<String>, lang: &str) {
+    if lang == "none" {
+        return;
+    }
+    if let Err(pos) = langs.binary_search_by(|l| l.as_str().cmp(lang)) {
+        langs.insert(pos, lang.to_string());
+    }
+}
+
+
+// An array of strings in the Postgres literal form, e.g. {"de","en"}, with
+// each element quoted and any quotes or backslashes within it escaped. An
+// empty list gives {}, the empty array.
+
+fn to_text_array_literal(values: &[String]) -> String {
+    let values: Vec<String> = values.iter()
+        .map(|v| format!("\"{}\"", v.replace('\\', "\\\\").replace('"', "\\\"")))
+        .collect();
+    format!("{{{}}}", values.join(","))
+}
+
+
+// An array of integers in the Postgres literal form, e.g. {1,2,3}. This is
+// also valid as a value in the COPY text format.
+
+fn to_int_array_literal(ids: &[i64]) -> String {
+    let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+    format!("{{{}}}", ids.join(","))
+}
+
+
+// Text format COPY uses tab and newline as delimiters and backslash as the
+// escape character, so these need to be escaped within any string value.
+
+fn push_copy_text(buf: &mut String, s: &str) {
+    for c in s.chars() {
+        match c {
+            '\\' => buf.push_str("\\\\"),
+            '\t' => buf.push_str("\\t"),
+            '\n' => buf.push_str("\\n"),
+            '\r' => buf.push_str("\\r"),
+            _ => buf.push(c),
+        }
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alt_rec(geo_id: i64, name: &str, lang: &str, historic: &str) -> AltRec {
+        let period: Vec<&str> = historic.split(',').filter(|p| !p.is_empty()).collect();
+        AltRec {
+            geo_id,
+            name: name.to_string(),
+            lang: lang.to_string(),
+            is_historic: !historic.is_empty(),
+            valid_from: None,
+            valid_to: None,
+            from_raw: period.first().map(|p| p.to_string()),
+            to_raw: period.get(1).map(|p| p.to_string()),
+            script: "Latn".to_string(),
+            is_preferred: false,
+            is_short: false,
+            is_colloquial: false,
+            alt_name_id: 0,
+        }
+    }
+
+    #[test]
+    fn check_langs_sorted_without_duplicates() {
+        let mut dv = AltRecVecs::new(10);
+        dv.add_data(&alt_rec(2643743, "London", "en", ""));
+        dv.add_data(&alt_rec(2643743, "Londres", "fr", ""));
+        dv.add_data(&alt_rec(2643743, "London", "none", ""));
+        dv.add_data(&alt_rec(2643743, "London", "de", ""));
+        dv.add_data(&alt_rec(2643743, "London", "en", ""));
+        dv.add_data(&alt_rec(2643743, "Lunnainn", "none", ""));
+        assert_eq!(dv.geo_ids.len(), 3);
+        assert_eq!(dv.names, vec!["London", "Londres", "Lunnainn"]);
+        assert_eq!(dv.langs, vec![vec!["de", "en"], vec!["fr"], vec![]]);
+    }
+
+    #[test]
+    fn check_flags_merged_across_langs() {
+        let mut dv = AltRecVecs::new(10);
+        let mut r = alt_rec(2643743, "London", "en", "");
+        r.is_preferred = true;
+        dv.add_data(&r);
+        dv.add_data(&alt_rec(2643743, "London", "none", ""));
+        let mut r = alt_rec(2643743, "London", "de", "");
+        r.is_preferred = true;
+        dv.add_data(&r);
+        let mut r = alt_rec(2643743, "The Smoke", "en", "");
+        r.is_colloquial = true;
+        dv.add_data(&r);
+        dv.add_data(&alt_rec(2643743, "The Smoke", "fr", ""));
+        assert_eq!(dv.preferreds, vec![true, false]);
+        assert_eq!(dv.preferred_langs, vec![vec!["de", "en"], vec![]]);
+        assert_eq!(dv.shorts, vec![false, false]);
+        assert_eq!(dv.colloquials, vec![false, true]);
+    }
+
+    #[test]
+    fn check_historic_and_current_names_kept_apart() {
+        let mut dv = AltRecVecs::new(10);
+        dv.add_data(&alt_rec(498817, "Leningrad", "en", "1924,1991"));
+        dv.add_data(&alt_rec(498817, "Leningrad", "de", "1924,1991"));
+        dv.add_data(&alt_rec(498817, "Leningrad", "ru", "1924,1990"));
+        dv.add_data(&alt_rec(498817, "Leningrad", "fr", ""));
+        dv.add_data(&alt_rec(2643743, "Leningrad", "fr", ""));
+        assert_eq!(dv.geo_ids.len(), 4);
+        assert_eq!(dv.langs, vec![vec!["de", "en"], vec!["ru"], vec!["fr"], vec!["fr"]]);
+        assert_eq!(dv.historics, vec![true, true, false, false]);
+        assert_eq!(dv.geo_ids, vec![498817, 498817, 498817, 2643743]);
+    }
+
+    #[test]
+    fn check_copy_text_plain_strings_unchanged() {
+        let mut buf = String::new();
+        push_copy_text(&mut buf, "Saint-Étienne");
+        assert_eq!(buf, "Saint-Étienne");
+    }
+
+    #[test]
+    fn check_copy_nulls_and_dates() {
+        let mut buf = String::new();
+        push_copy_opt_text(&mut buf, &None);
+        push_copy_date(&mut buf, &None);
+        push_copy_date(&mut buf, &NaiveDate::from_ymd_opt(1924, 1, 1));
+        assert_eq!(buf, "\\N\\N1924-01-01");
+    }
+
+    #[test]
+    fn check_copy_text_escapes_delimiters() {
+        let mut buf = String::new();
+        push_copy_text(&mut buf, "a\tb\nc\\d\re");
+        assert_eq!(buf, "a\\tb\\nc\\\\d\\re");
+    }
+
+    #[test]
+    fn check_codes_trimmed_and_upper_cased() {
+        let mut cv = CodeVecs::new(4);
+        cv.add_data(2643743, "unlc", "gblon ", 1);
+        cv.add_data(2647216, "iata", "LHR", 2);
+        assert_eq!(cv.codes, vec!["GBLON", "LHR"]);
+        assert_eq!(cv.code_types, vec!["unlc", "iata"]);
+    }
+
+    #[test]
+    fn check_postal_codes_normalised() {
+        assert_eq!(normalise_postal_code("SW1A 1AA"), "SW1A1AA");
+        assert_eq!(normalise_postal_code(" ec1a\t1bb "), "EC1A1BB");
+        assert_eq!(normalise_postal_code("75001"), "75001");
+    }
+
+    #[test]
+    fn check_source_ids_kept_for_aggregated_rows() {
+        let mut dv = AltRecVecs::new(10);
+        for (id, lang) in [(1620, "en"), (1621, "de"), (1622, "fr")] {
+            let mut r = alt_rec(2643743, "London", lang, "");
+            r.alt_name_id = id;
+            dv.add_data(&r);
+        }
+        let mut r = alt_rec(2643743, "Londres", "es", "");
+        r.alt_name_id = 1623;
+        dv.add_data(&r);
+        assert_eq!(dv.alt_name_ids, vec![vec![1620, 1621, 1622], vec![1623]]);
+        assert_eq!(to_int_array_literal(&dv.alt_name_ids[0]), "{1620,1621,1622}");
+    }
+
+    #[test]
+    fn check_text_array_literals() {
+        let langs = vec!["de".to_string(), "zh-Hant".to_string()];
+        assert_eq!(to_text_array_literal(&langs), "{\"de\",\"zh-Hant\"}");
+        assert_eq!(to_text_array_literal(&[]), "{}");
+        assert_eq!(to_text_array_literal(&["a\"b\\c".to_string()]), "{\"a\\\"b\\\\c\"}");
+    }
+}
@@ -0,0 +1,334 @@
+use sqlx::{Pool, Postgres, PgConnection};
+use crate::AppError;
+use crate::data_vectors::ImportBatch;
+use crate::import::{self, AltName, ImportOptions, RecordProcessor};
+use crate::source::{self, get_csv_reader};
+use crate::tables::GeoTables;
+use chrono::NaiveDate;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::path::Path;
+use log::info;
+
+// Applies the Geonames daily delta files to tables created by a full import.
+// Each day has an alternateNamesModifications-YYYY-MM-DD.txt file, with new
+// and changed records in the same format as the full file, and an
+// alternateNamesDeletes-YYYY-MM-DD.txt file, listing the alternate_name_ids
+// (and geoname ids) of deleted records. The source records in
+// geo.alt_src_names are updated, and every geoname with an added, changed
+// or deleted record then has all its rows rebuilt, from its source records,
+// using the same processing as the full import. Each day is applied within
+// a single transaction, and its date recorded in geo.applied_deltas.
+
+#[derive(Debug, PartialEq)]
+enum DeltaKind {
+    Modifications,
+    Deletes,
+}
+
+const MODIFICATIONS_PREFIX: &str = "alternateNamesModifications-";
+const DELETES_PREFIX: &str = "alternateNamesDeletes-";
+
+
+pub async fn apply_deltas(data_folder: &Path, pool: &Pool<Postgres>, opts: ImportOptions) -> Result<(), AppError> {
+
+    let tables = &opts.tables;
+    let sql = &format!("select to_regclass('{}') is not null", tables.name("alt_src_names"));
+    let has_sources: bool = sqlx::query_scalar(sql).fetch_one(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    if !has_sources {
+        return Err(AppError::PipelineError(format!("{} does not exist - a full import is required before deltas can be applied",
+                    tables.name("alt_src_names"))));
+    }
+
+    // Files dated on or before the latest delta already applied are skipped,
+    // as applying them would undo later changes.
+
+    let sql = &format!("select max(delta_date) from {}", tables.name("applied_deltas"));
+    let last_applied: Option<NaiveDate> = sqlx::query_scalar(sql).fetch_one(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+
+    // So are files dated on or before the full file last imported, as its
+    // records already include their changes.
+
+    let imported_up_to = get_imported_file_date(pool, tables).await?;
+
+    let mut applied = 0;
+    for date in find_delta_dates(data_folder)? {
+        if let Some(last) = last_applied.filter(|last| date <= *last) {
+            info!("Skipping delta for {}, as deltas up to {} have been applied", date, last);
+            continue;
+        }
+        if let Some(file_date) = imported_up_to.filter(|file_date| date <= *file_date) {
+            info!("Skipping delta for {}, as the full file imported was dated {}", date, file_date);
+            continue;
+        }
+        apply_delta(data_folder, date, pool, &opts).await?;
+        applied += 1;
+    }
+
+    info!("Applied {} delta(s)", applied);
+    Ok(())
+}
+
+
+// The modification date of the source file of the last completed import,
+// if there is an import_runs table and the date was recorded.
+
+async fn get_imported_file_date(pool: &Pool<Postgres>, tables: &GeoTables) -> Result<Option<NaiveDate>, AppError> {
+
+    let sql = &format!("select to_regclass('{}') is not null", tables.name("import_runs"));
+    let has_runs: bool = sqlx::query_scalar(sql).fetch_one(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    if !has_runs {
+        return Ok(None);
+    }
+
+    let sql = &format!(r#"select source_modified::date from {}
+                 where status = 'completed'
+                 order by run_id desc limit 1"#, tables.name("import_runs"));
+    let file_date: Option<Option<NaiveDate>> = sqlx::query_scalar(sql).fetch_optional(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    Ok(file_date.flatten())
+}
+
+
+async fn apply_delta(data_folder: &Path, date: NaiveDate, pool: &Pool<Postgres>, opts: &ImportOptions) -> Result<(), AppError> {
+
+    let tables = &opts.tables;
+    let date_string = date.format("%Y-%m-%d").to_string();
+    let mods_path = data_folder.join(format!("{}{}.txt", MODIFICATIONS_PREFIX, date_string));
+    let deletes_path = data_folder.join(format!("{}{}.txt", DELETES_PREFIX, date_string));
+
+    let deletes = match deletes_path.exists() {
+        true => read_deletes(&deletes_path)?,
+        false => Vec::new(),
+    };
+    let mods = match mods_path.exists() {
+        true => remove_deleted(read_modifications(&mods_path)?, &deletes),
+        false => Vec::new(),
+    };
+
+    let mut changed_ids: Vec<i64> = mods.iter().map(|m| m.alternate_name_id).collect();
+    changed_ids.extend(deletes.iter().map(|d| d.0));
+
+    let mut tx = pool.begin().await
+                .map_err(|e| AppError::SqlxError(e, "begin transaction".to_string()))?;
+
+    // The geonames affected include those that a changed record belonged to
+    // before the change, as well as those it belongs to now.
+
+    let sql = &format!("select distinct geoname_id::int8 from {} where alternate_name_id = any($1)", tables.name("alt_src_names"));
+    let old_geo_ids: Vec<i64> = sqlx::query_scalar(sql).bind(&changed_ids).fetch_all(&mut *tx).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+
+    let geo_ids: Vec<i64> = old_geo_ids.into_iter()
+                .chain(mods.iter().map(|m| m.geoname_id))
+                .chain(deletes.iter().map(|d| d.1))
+                .collect::<BTreeSet<i64>>().into_iter().collect();
+
+    let sql = format!("delete from {} where alternate_name_id = any($1)", tables.name("alt_src_names"));
+    execute_with_ids(&sql, &changed_ids, &mut tx).await?;
+
+    let mut sources = ImportBatch::new(mods.len());
+    for m in &mods {
+        sources.sources.add_data(m);
+    }
+    sources.store_data(&mut tx, tables, opts.load_mode).await?;
+
+    // All the derived rows of the affected geonames are removed, and then
+    // rebuilt from their (now updated) source records.
+
+    let mut derived = vec!["alt_names", "wikidata_xref", "links", "codes"];
+    if opts.postal_codes {
+        derived.push("postal_codes");
+    }
+    for table in derived {
+        let id_col = if table == "alt_names" { "id" } else { "geoname_id" };
+        let sql = format!("delete from {} where {} = any($1)", tables.name(table), id_col);
+        execute_with_ids(&sql, &geo_ids, &mut tx).await?;
+    }
+
+    let batch = rebuild_geonames(&geo_ids, opts, &mut tx).await?;
+    batch.store_data(&mut tx, tables, opts.load_mode).await?;
+
+    let sql = &format!(r#"insert into {} (delta_date, modified_count, deleted_count, geoname_count)
+                 values ($1, $2, $3, $4)"#, tables.name("applied_deltas"));
+    sqlx::query(sql)
+        .bind(date).bind(mods.len() as i32).bind(deletes.len() as i32).bind(geo_ids.len() as i32)
+        .execute(&mut *tx).await
+        .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+
+    tx.commit().await
+        .map_err(|e| AppError::SqlxError(e, "commit transaction".to_string()))?;
+
+    info!("Applied delta for {}: {} modified and {} deleted records, {} geonames rebuilt",
+            date_string, mods.len(), deletes.len(), geo_ids.len());
+    Ok(())
+}
+
+
+async fn rebuild_geonames(geo_ids: &[i64], opts: &ImportOptions, conn: &mut PgConnection) -> Result<ImportBatch, AppError> {
+
+    let sql = &format!(r#"select alternate_name_id, geoname_id::int8, iso_language, alternate_name,
+                 is_preferred_name, is_short_name, is_colloquial, is_historic, yfrom, yto
+                 from {}
+                 where geoname_id = any($1)
+                 order by geoname_id, alternate_name_id"#, opts.tables.name("alt_src_names"));
+
+    type SrcRow = (i64, i64, Option<String>, String, bool, bool, bool, bool, Option<String>, Option<String>);
+    let rows: Vec<SrcRow> = sqlx::query_as(sql).bind(geo_ids).fetch_all(&mut *conn).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+
+    let mut batch = ImportBatch::new(rows.len());
+    let mut processor = RecordProcessor::new(opts);
+    for r in rows {
+        let source = AltName {
+            alternate_name_id: r.0,
+            geoname_id: r.1,
+            iso_language: r.2,
+            alternate_name: r.3,
+            is_preferred_name: to_flag(r.4),
+            is_short_name: to_flag(r.5),
+            is_colloquial: to_flag(r.6),
+            is_historic: to_flag(r.7),
+            yfrom: r.8,
+            yto: r.9,
+        };
+        if let Some(alt_name) = processor.process(source, &mut batch) {
+            batch.alt_names.add_data(&alt_name);
+        }
+    }
+    processor.log_summary();
+
+    Ok(batch)
+}
+
+
+fn to_flag(b: bool) -> Option<usize> {
+    if b { Some(1) } else { None }
+}
+
+
+async fn execute_with_ids(sql: &str, ids: &[i64], conn: &mut PgConnection) -> Result<u64, AppError> {
+    sqlx::query(sql).bind(ids).execute(conn).await
+        .map(|r| r.rows_affected())
+        .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+}
+
+
+// A record may be modified more than once in a file, in which case only
+// the last version is kept.
+
+fn read_modifications(path: &Path) -> Result<Vec<AltName>, AppError> {
+
+    let layout = source::detect_layout(path)?;
+    let mut csv_rdr = get_csv_reader(path)?;
+    let mut mods: HashMap<i64, AltName> = HashMap::new();
+    let mut record = csv::StringRecord::new();
+    while csv_rdr.read_record(&mut record)? {
+        let source = import::to_alt_name(&record, layout)?;
+        mods.insert(source.alternate_name_id, source);
+    }
+    Ok(mods.into_values().collect())
+}
+
+
+// A record both modified and deleted on the same day is deleted, as the
+// deletion is the later change.
+
+fn remove_deleted(mods: Vec<AltName>, deletes: &[(i64, i64)]) -> Vec<AltName> {
+
+    let deleted_ids: HashSet<i64> = deletes.iter().map(|d| d.0).collect();
+    mods.into_iter().filter(|m| !deleted_ids.contains(&m.alternate_name_id)).collect()
+}
+
+
+// The deletes file has the alternate_name_id, geoname id, name and a comment.
+
+fn read_deletes(path: &Path) -> Result<Vec<(i64, i64)>, AppError> {
+
+    let mut csv_rdr = get_csv_reader(path)?;
+    let mut record = csv::StringRecord::new();
+    let mut deletes = Vec::new();
+    while csv_rdr.read_record(&mut record)? {
+        let alt_name_id = record.get(0).unwrap_or("").parse::<i64>();
+        let geo_id = record.get(1).unwrap_or("").parse::<i64>();
+        match (alt_name_id, geo_id) {
+            (Ok(a), Ok(g)) => deletes.push((a, g)),
+            _ => return Err(AppError::PipelineError(format!("Invalid ids in record {} of {}",
+                            csv_rdr.position().record(), path.display()))),
+        }
+    }
+    Ok(deletes)
+}
+
+
+fn find_delta_dates(data_folder: &Path) -> Result<BTreeSet<NaiveDate>, AppError> {
+
+    let mut dates = BTreeSet::new();
+    let entries = std::fs::read_dir(data_folder)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, data_folder.to_owned()))?;
+    for entry in entries {
+        let file_name = entry?.file_name();
+        if let Some((_, date)) = parse_delta_file_name(&file_name.to_string_lossy()) {
+            dates.insert(date);
+        }
+    }
+    Ok(dates)
+}
+
+
+fn parse_delta_file_name(file_name: &str) -> Option<(DeltaKind, NaiveDate)> {
+
+    let (kind, rest) = if let Some(rest) = file_name.strip_prefix(MODIFICATIONS_PREFIX) {
+        (DeltaKind::Modifications, rest)
+    }
+    else if let Some(rest) = file_name.strip_prefix(DELETES_PREFIX) {
+        (DeltaKind::Deletes, rest)
+    }
+    else {
+        return None;
+    };
+
+    let date = NaiveDate::parse_from_str(rest.strip_suffix(".txt")?, "%Y-%m-%d").ok()?;
+    Some((kind, date))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_delta_file_names_parsed() {
+        let date = NaiveDate::from_ymd_opt(2025, 1, 23).unwrap();
+        assert_eq!(parse_delta_file_name("alternateNamesModifications-2025-01-23.txt"), Some((DeltaKind::Modifications, date)));
+        assert_eq!(parse_delta_file_name("alternateNamesDeletes-2025-01-23.txt"), Some((DeltaKind::Deletes, date)));
+    }
+
+    #[test]
+    fn check_deleted_modifications_removed() {
+        let alt_name = |alternate_name_id: i64| AltName {
+            alternate_name_id,
+            geoname_id: 2643743,
+            iso_language: Some("en".to_string()),
+            alternate_name: "London".to_string(),
+            is_preferred_name: None,
+            is_short_name: None,
+            is_colloquial: None,
+            is_historic: None,
+            yfrom: None,
+            yto: None,
+        };
+        let mods = remove_deleted(vec![alt_name(1), alt_name(2), alt_name(3)], &[(2, 2643743), (4, 2643743)]);
+        assert_eq!(mods.iter().map(|m| m.alternate_name_id).collect::<Vec<i64>>(), vec![1, 3]);
+    }
+
+    #[test]
+    fn check_other_file_names_ignored() {
+        assert_eq!(parse_delta_file_name("alternateNamesV2.txt"), None);
+        assert_eq!(parse_delta_file_name("alternateNamesDeletes-2025-01-32.txt"), None);
+        assert_eq!(parse_delta_file_name("alternateNamesDeletes-2025-01-23.zip"), None);
+        assert_eq!(parse_delta_file_name("modifications-2025-01-23.txt"), None);
+    }
+}
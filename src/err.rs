@@ -42,6 +42,15 @@ pub enum AppError {
 
     #[error("Error during CSV read operation: {0:?}")]
     CsvError(#[from] csv::Error),
+
+    #[error("Error in the import pipeline: {0:?}")]
+    PipelineError(String),
+
+    #[error("Unable to read source file {1:?}: {0:?}")]
+    SourceFileError(String, std::path::PathBuf),
+
+    #[error("Schema version mismatch: {0:?}")]
+    SchemaVersionError(String),
 }
 
 
@@ -80,6 +89,13 @@ pub fn report_error(e: AppError) -> () {
         AppError::IoError(e) => print_simple_error (e.to_string(), "IO ERROR"),
 
         AppError::CsvError(e) => print_simple_error (e.to_string(), "CSV ERROR"),
+
+        AppError::PipelineError(d) => print_simple_error (d, "IMPORT PIPELINE ERROR"),
+
+        AppError::SourceFileError(d, p) => print_error (d, 
+                  "Path was: ".to_string() + &p.to_string_lossy(), "SOURCE FILE ERROR"),
+
+        AppError::SchemaVersionError(d) => print_simple_error (d, "SCHEMA VERSION ERROR"),
     }
 }
 
@@ -1,8 +1,54 @@
 use sqlx::{Pool, Postgres};
+use sqlx::postgres::PgPoolCopyExt;
+use futures_util::StreamExt;
 use crate::AppError;
-use std::path::PathBuf;
+use crate::tables::GeoTables;
+use std::path::{Path, PathBuf};
+use std::io::{BufWriter, Write};
+use std::fs::File;
+use chrono::Local;
+use log::info;
 
-pub async fn export_data(_data_folder: &PathBuf, _source_file_name: &String, _pool: &Pool<Postgres>) -> Result<(), AppError> {
 
+pub async fn export_data(output_folder: &Path, source_file_name: &str, tables: &GeoTables, pool: &Pool<Postgres>) -> Result<(), AppError> {
+
+    // The output file is named after the source file, with a timestamp added,
+    // so that successive exports do not overwrite each other.
+
+    let source_stem = PathBuf::from(source_file_name).file_stem()
+                        .map(|s| s.to_string_lossy().to_string())
+                        .unwrap_or_else(|| "geonames".to_string());
+    let datetime_string = Local::now().format("%Y-%m-%d %H%M%S").to_string();
+    let output_file_name = format!("{} alt names at {}.csv", source_stem, datetime_string);
+    let output_file_path: PathBuf = output_folder.join(output_file_name);
+
+    let file = File::create(&output_file_path)
+                    .map_err(|e| AppError::IoWriteErrorWithPath(e, output_file_path.clone()))?;
+    let mut writer = BufWriter::new(file);
+
+    // The table is streamed out of Postgres using the COPY protocol, with the
+    // database generating the CSV. Each chunk received is written straight to
+    // the file, so the full table is never held in memory.
+
+    let sql = &format!(r#"COPY (select id, alt_name, langs, is_historic, valid_from, valid_to, from_raw, to_raw, script,
+                 is_preferred_name, preferred_langs, is_short_name, is_colloquial, alternate_name_ids
+                 from {}
+                 order by id, alt_name)
+                 TO STDOUT WITH (FORMAT csv, HEADER true)"#, tables.name("alt_names"));
+
+    let mut stream = pool.copy_out_raw(sql).await
+                    .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+
+    let mut bytes_written: usize = 0;
+    while let Some(chunk) = stream.next().await {
+        let chunk = chunk.map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+        writer.write_all(&chunk)
+            .map_err(|e| AppError::IoWriteErrorWithPath(e, output_file_path.clone()))?;
+        bytes_written += chunk.len();
+    }
+
+    writer.flush().map_err(|e| AppError::IoWriteErrorWithPath(e, output_file_path.clone()))?;
+
+    info!("Exported {} ({} bytes) to {}", tables.name("alt_names"), bytes_written, output_file_path.display());
     Ok(())
-}
\ No newline at end of file
+}
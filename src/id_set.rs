@@ -0,0 +1,122 @@
+// A compact set of geoname ids, held as a bit vector indexed by id.
+// Geoname ids are positive integers, currently a little over 13 million,
+// so the full range can be tracked with less than 2MB of memory.
+
+pub struct IdSet {
+    bits: Vec<u64>,
+}
+
+impl IdSet {
+    pub fn new() -> Self {
+        IdSet {
+            bits: Vec::new(),
+        }
+    }
+
+    // Returns true if the id was not already present.
+
+    pub fn insert(&mut self, id: i64) -> bool {
+        let (word, mask) = Self::position(id);
+        if word >= self.bits.len() {
+            self.bits.resize(word + 1, 0);
+        }
+        let is_new = self.bits[word] & mask == 0;
+        self.bits[word] |= mask;
+        is_new
+    }
+
+    pub fn contains(&self, id: i64) -> bool {
+        let (word, mask) = Self::position(id);
+        word < self.bits.len() && self.bits[word] & mask != 0
+    }
+
+    fn position(id: i64) -> (usize, u64) {
+        let id = id.max(0) as usize;
+        (id / 64, 1 << (id % 64))
+    }
+}
+
+impl Default for IdSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+// Tracks the sequence of geoname ids as the source file is read, identifying
+// those ids whose records do not all appear in a single contiguous run.
+
+pub struct ContiguityCheck {
+    seen: IdSet,
+    pub scattered: IdSet,
+    pub scattered_count: u64,
+    last_id: Option<i64>,
+}
+
+impl ContiguityCheck {
+    pub fn new() -> Self {
+        ContiguityCheck {
+            seen: IdSet::new(),
+            scattered: IdSet::new(),
+            scattered_count: 0,
+            last_id: None,
+        }
+    }
+
+    pub fn add(&mut self, id: i64) {
+        if self.last_id == Some(id) {
+            return;
+        }
+        if !self.seen.insert(id) && self.scattered.insert(id) {
+            self.scattered_count += 1;
+        }
+        self.last_id = Some(id);
+    }
+}
+
+impl Default for ContiguityCheck {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_id_set_insert_and_contains() {
+        let mut ids = IdSet::new();
+        assert!(ids.insert(2643743));
+        assert!(!ids.insert(2643743));
+        assert!(ids.insert(63));
+        assert!(ids.insert(64));
+        assert!(ids.contains(2643743));
+        assert!(ids.contains(63));
+        assert!(ids.contains(64));
+        assert!(!ids.contains(65));
+        assert!(!ids.contains(13000000));
+    }
+
+    #[test]
+    fn check_contiguous_ids_not_reported() {
+        let mut check = ContiguityCheck::new();
+        for id in [5, 5, 5, 7, 7, 9] {
+            check.add(id);
+        }
+        assert_eq!(check.scattered_count, 0);
+    }
+
+    #[test]
+    fn check_scattered_ids_counted_once() {
+        let mut check = ContiguityCheck::new();
+        for id in [5, 5, 7, 5, 9, 7, 5, 5] {
+            check.add(id);
+        }
+        assert_eq!(check.scattered_count, 2);
+        assert!(check.scattered.contains(5));
+        assert!(check.scattered.contains(7));
+        assert!(!check.scattered.contains(9));
+    }
+}
@@ -1,16 +1,39 @@
-use sqlx::{postgres::PgQueryResult, Pool, Postgres};
+use sqlx::{Pool, Postgres};
 use crate::AppError;
-use std::path::PathBuf;
-use std::io::BufReader;
-use std::fs::File;
-use csv::ReaderBuilder;
-use crate::data_vectors::AltRecVecs;
-use log::info;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use crate::data_vectors::{ImportBatch, LoadMode};
+use crate::id_set::{ContiguityCheck, IdSet};
+use crate::scripts::{self, ScriptFilter};
+use crate::langs::LangFilter;
+use crate::source::{self, get_csv_reader, SourceLayout};
+use crate::checkpoint::{self, Checkpoint, PrefixHasher, ResumePoint};
+use crate::tables::GeoTables;
+use crate::periods::{self, Bound};
+use chrono::NaiveDate;
+use std::collections::HashMap;
+use log::{info, warn};
 
 
 #[derive(serde::Deserialize)]
-#[allow(dead_code)]
-struct AltName {
+pub struct AltName {
+    pub alternate_name_id: i64,
+    pub geoname_id: i64,
+    pub iso_language: Option<String>,
+    pub alternate_name: String,
+    pub is_preferred_name: Option<usize>,
+    pub is_short_name: Option<usize>,
+    pub is_colloquial: Option<usize>,
+    pub is_historic: Option<usize>,
+    pub yfrom: Option<String>,
+    pub yto: Option<String>,
+}
+
+// The v1 layout lacks the from and to columns.
+
+#[derive(serde::Deserialize)]
+struct AltNameV1 {
     alternate_name_id: i64,
     geoname_id: i64,
     iso_language: Option<String>,
@@ -19,8 +42,32 @@ struct AltName {
     is_short_name: Option<usize>,
     is_colloquial: Option<usize>,
     is_historic: Option<usize>,
-    yfrom: Option<String>,
-    yto: Option<String>,
+}
+
+impl From<AltNameV1> for AltName {
+    fn from(v1: AltNameV1) -> Self {
+        AltName {
+            alternate_name_id: v1.alternate_name_id,
+            geoname_id: v1.geoname_id,
+            iso_language: v1.iso_language,
+            alternate_name: v1.alternate_name,
+            is_preferred_name: v1.is_preferred_name,
+            is_short_name: v1.is_short_name,
+            is_colloquial: v1.is_colloquial,
+            is_historic: v1.is_historic,
+            yfrom: None,
+            yto: None,
+        }
+    }
+}
+
+// Deserializes a source record of either layout into an AltName.
+
+pub fn to_alt_name(record: &csv::StringRecord, layout: SourceLayout) -> Result<AltName, csv::Error> {
+    match layout {
+        SourceLayout::V2 => record.deserialize(None),
+        SourceLayout::V1 => record.deserialize::<AltNameV1>(None).map(AltName::from),
+    }
 }
 
 #[derive(Debug)]
@@ -28,152 +75,529 @@ pub struct AltRec {
     pub geo_id: i64,
     pub name: String,
     pub lang: String,
-    pub historic: String,
-}
-
-
-pub async fn import_data(data_folder: &PathBuf, source_file_name: &String, pool: &Pool<Postgres>, latin_only: bool) -> Result<(), AppError> {
-
-    let source_file_path: PathBuf = [data_folder, &PathBuf::from(source_file_name)].iter().collect();
-    let file = File::open(source_file_path)?;
-    let buf_reader = BufReader::new(file);
-    let mut csv_rdr = ReaderBuilder::new()
-        .has_headers(false)
-        .delimiter(9)
-        .from_reader(buf_reader);
-    
-   let mut i = 0;
-   let mut gid_num = 0;
-   let mut old_gid = 0;
-
-   let none = "none".to_string();
-   let link = "link".to_string();
-   let wkdt = "wkdt".to_string();
-   let post = "post".to_string();
-   let unlc = "unlc".to_string();
-   let faac = "faac".to_string();
-   let icao = "icao".to_string();
-   let iata = "iata".to_string();
-   let abbr = "abbr".to_string();
-   let end_of_latin = "ZZ".to_string();
-
-   let vector_size = 2500;
-   let mut dv: AltRecVecs = AltRecVecs::new(vector_size);
-   create_collecting_table(&pool).await?;
-
-   for result in csv_rdr.deserialize() {
-
-        let source: AltName = result?;
-        let mut create_rec = true;
-        let lang_code = source.iso_language.unwrap_or(none.clone());
-        
-        if lang_code != none {
-            if lang_code == link || lang_code == wkdt
-            || lang_code == post || lang_code == unlc
-            || lang_code == faac || lang_code == icao
-            || lang_code == iata || lang_code == abbr
-            {
-                create_rec = false;
+    pub is_historic: bool,
+    pub valid_from: Option<NaiveDate>,
+    pub valid_to: Option<NaiveDate>,
+    pub from_raw: Option<String>,
+    pub to_raw: Option<String>,
+    pub script: String,
+    pub is_preferred: bool,
+    pub is_short: bool,
+    pub is_colloquial: bool,
+    pub alt_name_id: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ImportOptions {
+    pub script_filter: ScriptFilter,
+    pub lang_filter: LangFilter,
+    pub load_mode: LoadMode,
+    pub exact: bool,
+    pub postal_codes: bool,
+    pub resume: bool,
+    pub tables: GeoTables,
+}
+
+// The number of batches that can be waiting for the database before the
+// reader thread is made to pause (the back pressure on the parsing), and
+// the number of tasks writing batches to the database. Batches hold final,
+// already aggregated rows, so they can be stored in any order. Each is sent
+// with the checkpoint to be recorded when it has been stored.
+
+const CHANNEL_CAPACITY: usize = 4;
+const WRITER_COUNT: usize = 2;
+
+// Unparseable from / to values are logged individually up to this number,
+// after which they are only counted.
+
+const MAX_LOGGED_PERIOD_ERRORS: u64 = 50;
+
+
+pub async fn import_data(data_folder: &Path, source_file_name: &str, pool: &Pool<Postgres>, opts: ImportOptions) -> Result<ImportSummary, AppError> {
+
+    // The import is run as a pipeline. The source file is read and parsed on a
+    // blocking thread, which builds batches of records and passes them through
+    // a bounded channel to the database writer task(s). Parsing therefore
+    // continues while earlier batches are being stored. The batches are
+    // stored in the staging tables, which are swapped in by the caller.
+
+    let source_file_path: PathBuf = data_folder.join(source_file_name);
+    let staging = opts.tables.staging();
+
+    let resume_point = match opts.resume {
+        true => checkpoint::fetch_resume_point(pool, &staging, source_file_name).await?,
+        false => ResumePoint::new(),
+    };
+
+    let (tx, rx) = mpsc::channel::<(Checkpoint, ImportBatch)>(CHANNEL_CAPACITY);
+    let rx = Arc::new(Mutex::new(rx));
+
+    let load_mode = opts.load_mode;
+    let reader = tokio::task::spawn_blocking(move || read_source_file(&source_file_path, opts, resume_point, tx));
+
+    let source_file_name = Arc::new(source_file_name.to_string());
+    let mut writers = Vec::with_capacity(WRITER_COUNT);
+    for _ in 0..WRITER_COUNT {
+        writers.push(tokio::spawn(write_batches(rx.clone(), pool.clone(), load_mode, staging.clone(), source_file_name.clone())));
+    }
+
+    // Only the writers may hold the receiver. If they all fail it is then
+    // dropped, and the reader's next send fails rather than blocking forever.
+
+    drop(rx);
+
+    // A database error is reported in preference to the reader's error, as
+    // the reader will only have stopped because the writers went away.
+
+    let read_result = reader.await
+                .map_err(|e| AppError::PipelineError(format!("Source file reader failed: {}", e)))?;
+    for writer in writers {
+        writer.await
+            .map_err(|e| AppError::PipelineError(format!("Database writer failed: {}", e)))??;
+    }
+    let summary = read_result?;
+
+    info!("Processed {} alternate name records in total", summary.records_read);
+    Ok(summary)
+}
+
+
+fn read_source_file(source_file_path: &Path, opts: ImportOptions, resume_point: ResumePoint,
+                    tx: mpsc::Sender<(Checkpoint, ImportBatch)>) -> Result<ImportSummary, AppError> {
+
+    // Batches are cut at a change of geoname id, which assumes that all the
+    // records for an id are adjacent. In exact mode the file is first scanned
+    // to find any ids for which that is not true. Records for those ids are
+    // then held back and aggregated across the whole file, being sent as a
+    // final batch, so that each name still produces a single row.
+
+    let scattered_ids = match opts.exact {
+        true => Some(find_scattered_ids(source_file_path)?),
+        false => None,
+    };
+    let layout = source::detect_layout(source_file_path)?;
+    info!("Source file layout: {}", layout.describe());
+
+    let mut held = ImportBatch::new(1000);   // only the alt_names part is used
+    let mut check = ContiguityCheck::new();
+    let mut processor = RecordProcessor::new(&opts);
+
+    let mut i = 0;
+    let mut gid_num = 0;
+    let mut old_gid = 0;
+    let mut batch_no = 1;
+
+    // When resuming, reading starts after the last of the unbroken run of
+    // completed batches, with the state that a full run would have had at
+    // that point. The held records of exact mode may be anywhere in the file,
+    // so in that mode the file is read from the start, with the batches
+    // already stored being skipped.
+
+    // The data before the resume point must be the data that was imported.
+    // Checkpoints stored without a hash can only be checked by geoname id.
+
+    let io_error = |e: std::io::Error| AppError::IoReadErrorWithPath(e, source_file_path.to_owned());
+    let mut prefix_hasher = PrefixHasher::new(source::get_data_reader(source_file_path)?);
+    if let Some(cp) = &resume_point.last_contiguous {
+        if let Some(stored) = &cp.prefix_sha256 {
+            if prefix_hasher.hash_to(cp.end_offset as u64).map_err(io_error)? != *stored {
+                return Err(AppError::PipelineError(format!("The source file has changed since the import was interrupted \
+                            (the data before byte {} is not the data imported)", cp.end_offset)));
             }
         }
+    }
+
+    let mut start_offset = 0;
+    let mut expected_gid = None;
+    if let (Some(cp), false) = (&resume_point.last_contiguous, opts.exact) {
+        start_offset = cp.end_offset as u64;
+        expected_gid = cp.next_geoname_id;
+        old_gid = cp.next_geoname_id.unwrap_or(0);
+        batch_no = cp.batch_no + 1;
+        info!("Resuming the import after batch {}, at byte {}", cp.batch_no, cp.end_offset);
+    }
+    let mut skipped = 0;
+
+    let mut csv_rdr = source::get_csv_reader_from(source_file_path, start_offset)?;
 
-        // Optional filter here to exclude non Latin names.
+    let vector_size = 2500;
+    let mut dv = ImportBatch::new(vector_size);
+    let mut record = csv::StringRecord::new();
 
-        if latin_only {
-            if source.alternate_name > end_of_latin {
-                create_rec = false;
+    while csv_rdr.read_record(&mut record)? {
+
+        let source = to_alt_name(&record, layout)?;
+        check.add(source.geoname_id);
+
+        if let Some(gid) = expected_gid.take() {
+            if gid != source.geoname_id {
+                return Err(AppError::PipelineError(format!("The source file has changed since the import was interrupted \
+                            (expected geoname id {} at byte {}, found {})", gid, start_offset, source.geoname_id)));
             }
         }
 
-        if create_rec {
-
-            let geo_id = source.geoname_id;
-            if geo_id != old_gid {
-                gid_num = gid_num + 1;
+        let geo_id = source.geoname_id;
+        let is_held = scattered_ids.as_ref().is_some_and(|ids| ids.contains(geo_id));
+        if geo_id != old_gid && !is_held {
+            gid_num += 1;
 
-                if gid_num == 2500 {  // every 2500 geoname ids
+            if gid_num == 2500 {  // every 2500 geoname ids
 
-                    // Call the routine to transfer records to the database.
-                    // Recreate the vectors, reset gid_num.
-                    // Then aggregate lang codes and recreate the collecting table.
+                // Pass the completed batch to the writer(s), unless it was
+                // stored before an interruption. The checkpoint's offset is
+                // the start of the current record, the first of the next batch.
+                // Recreate the vectors, reset gid_num.
 
-                    dv.store_data(&pool).await?;
-                    dv = AltRecVecs::new(vector_size);
-                    gid_num = 0;
-                    transfer_data(&pool).await?;
-                    create_collecting_table(&pool).await?;
-                }
-                
-                old_gid = geo_id;
+                let offset = start_offset + record.position().map_or(0, |p| p.byte());
+                let cp = get_checkpoint(batch_no, offset, Some(geo_id), &mut prefix_hasher, &resume_point).map_err(io_error)?;
+                skipped += send_batch(&tx, cp, dv, &resume_point)?;
+                dv = ImportBatch::new(vector_size);
+                gid_num = 0;
+                batch_no += 1;
             }
 
-            let mut is_historic = "".to_string();
-            if source.is_historic.is_some() && source.is_historic.unwrap() == 1 {
-                is_historic = "Historic".to_string();
-                 if source.yfrom.is_some() {
-                    is_historic = is_historic + ", from " + &source.yfrom.unwrap();
-                 }
-                 if source.yto.is_some() {
-                    is_historic = is_historic + ", to " + &source.yto.unwrap();
-                  }
+            old_gid = geo_id;
+        }
+
+        // Every record is kept in geo.alt_src_names, then processed into
+        // the alt_names row(s) and / or the rows of the other tables.
+
+        dv.sources.add_data(&source);
+        if let Some(alt_name) = processor.process(source, &mut dv) {
+            if is_held {
+                held.alt_names.add_data(&alt_name);
             }
-           
-            let alt_name = AltRec {
-                geo_id: source.geoname_id,
-                name: source.alternate_name,
-                lang: lang_code.clone(),
-                historic: is_historic,
-            };
-
-            // transfer data to vectors
-            dv.add_data(&alt_name);
-        }
-        
-        i +=1;
+            else {
+                dv.alt_names.add_data(&alt_name);
+            }
+        }
 
-        //if i > 3000 {
-        //    break;
-        // }
+        i +=1;
 
         if i % 250000 == 0 {
             info!("Processed {} alternate name records", i);
         }
     }
 
-    dv.store_data(&pool).await?;
-    transfer_data(&pool).await?;
-            
-    Ok(())
+    let end_offset = start_offset + csv_rdr.position().byte();
+    let cp = get_checkpoint(batch_no, end_offset, None, &mut prefix_hasher, &resume_point).map_err(io_error)?;
+    skipped += send_batch(&tx, cp, dv, &resume_point)?;
+    let cp = get_checkpoint(batch_no + 1, end_offset, None, &mut prefix_hasher, &resume_point).map_err(io_error)?;
+    skipped += send_batch(&tx, cp, held, &resume_point)?;
+    if skipped > 0 {
+        info!("Skipped {} batches stored before the import was interrupted", skipped);
+    }
+
+    processor.log_summary();
+
+    if check.scattered_count > 0 {
+        if opts.exact {
+            info!("{} geoname ids had non-contiguous records, aggregated as a final batch", check.scattered_count);
+        }
+        else {
+            warn!("{} geoname ids had non-contiguous records, and may have more than one row for a name - use exact mode (-e) to avoid this", check.scattered_count);
+        }
+    }
+    Ok(processor.into_summary(i))
+}
+
+
+// The counts of the records read by an import, and of those kept, dropped
+// (by language code or by script) or kept with unparseable from / to values.
+// A resumed import only counts the records read after it resumed.
+
+#[derive(Debug, Default)]
+pub struct ImportSummary {
+    pub records_read: u64,
+    pub records_kept: u64,
+    pub lang_drops: HashMap<String, u64>,
+    pub script_drops: HashMap<&'static str, u64>,
+    pub period_errors: u64,
+}
+
+impl ImportSummary {
+    pub fn lang_drop_count(&self) -> u64 {
+        self.lang_drops.values().sum()
+    }
+
+    pub fn script_drop_count(&self) -> u64 {
+        self.script_drops.values().sum()
+    }
+}
+
+
+// Turns source records into the rows to be stored, applying the language
+// and script filters, and keeps count of the records kept, dropped or only
+// partly parsed. Used both for the full import and when applying deltas.
+
+pub struct RecordProcessor<'a> {
+    opts: &'a ImportOptions,
+    kept: u64,
+    lang_drops: HashMap<String, u64>,
+    script_drops: HashMap<&'static str, u64>,
+    period_errors: u64,
+}
+
+impl<'a> RecordProcessor<'a> {
+    pub fn new(opts: &'a ImportOptions) -> Self {
+        RecordProcessor {
+            opts,
+            kept: 0,
+            lang_drops: HashMap::new(),
+            script_drops: HashMap::new(),
+            period_errors: 0,
+        }
+    }
+
+    // Adds any Wikidata, link, transport code or postal code rows to the
+    // batch, and returns the record as an AltRec if it is to be included in
+    // geo.alt_names. The caller decides which batch that goes into.
+
+    pub fn process(&mut self, source: AltName, dv: &mut ImportBatch) -> Option<AltRec> {
+
+        let geo_id = source.geoname_id;
+        let lang_code = source.iso_language.unwrap_or_else(|| "none".to_string());
+
+        // Wikidata QIDs, URLs, transport codes and (unless skipped) postal
+        // codes are routed to their own tables, whether or not these records
+        // are also included as names.
+
+        match lang_code.as_str() {
+            "wkdt" => dv.wikidata.add_data(geo_id, &source.alternate_name, source.alternate_name_id),
+            "link" => dv.links.add_data(geo_id, &source.alternate_name, source.alternate_name_id),
+            "iata" | "icao" | "faac" | "unlc" => dv.codes.add_data(geo_id, &lang_code, &source.alternate_name, source.alternate_name_id),
+            "post" if self.opts.postal_codes => dv.postal_codes.add_data(geo_id, &source.alternate_name, source.alternate_name_id),
+            _ => (),
+        }
+
+        // Exclude records whose language code is not wanted (by default
+        // the pseudo-language codes, such as 'link' and 'post').
+
+        if !self.opts.lang_filter.accepts(&lang_code) {
+            *self.lang_drops.entry(lang_code).or_insert(0) += 1;
+            return None;
+        }
+
+        // Exclude names in scripts that are not wanted, counting the names
+        // dropped against the first such script.
+
+        let name_scripts = scripts::get_scripts(&source.alternate_name);
+        if let Some(script) = self.opts.script_filter.rejected_from(&name_scripts) {
+            *self.script_drops.entry(script.full_name()).or_insert(0) += 1;
+            return None;
+        }
+
+        // The from and to values are parsed into dates, with the raw
+        // values also retained. Values that cannot be parsed are logged
+        // (up to a limit) and counted.
+
+        let valid_from = parse_period_value(&source.yfrom, Bound::Start, source.alternate_name_id, &mut self.period_errors);
+        let valid_to = parse_period_value(&source.yto, Bound::End, source.alternate_name_id, &mut self.period_errors);
+
+        self.kept += 1;
+        Some(AltRec {
+            geo_id,
+            name: source.alternate_name,
+            lang: lang_code,
+            is_historic: source.is_historic == Some(1),
+            valid_from,
+            valid_to,
+            from_raw: source.yfrom,
+            to_raw: source.yto,
+            script: scripts::get_script_code(&name_scripts),
+            is_preferred: source.is_preferred_name == Some(1),
+            is_short: source.is_short_name == Some(1),
+            is_colloquial: source.is_colloquial == Some(1),
+            alt_name_id: source.alternate_name_id,
+        })
+    }
+
+    pub fn log_summary(&self) {
+
+        let mut lang_drops: Vec<(&String, &u64)> = self.lang_drops.iter().collect();
+        lang_drops.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (lang, n) in lang_drops {
+            info!("Dropped {} records with language code {}", n, lang);
+        }
+
+        let mut script_drops: Vec<(&&str, &u64)> = self.script_drops.iter().collect();
+        script_drops.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (script, n) in script_drops {
+            info!("Dropped {} names in {} script", n, script);
+        }
+
+        if self.period_errors > 0 {
+            warn!("{} from / to values could not be parsed as years or dates, and have been stored as raw values only", self.period_errors);
+        }
+    }
+
+    pub fn into_summary(self, records_read: u64) -> ImportSummary {
+        ImportSummary {
+            records_read,
+            records_kept: self.kept,
+            lang_drops: self.lang_drops,
+            script_drops: self.script_drops,
+            period_errors: self.period_errors,
+        }
+    }
 }
 
 
-async fn create_collecting_table(pool: &Pool<Postgres>) -> Result<PgQueryResult, AppError> {
-    
-    let sql = r#"drop table if exists geo.alt_src_names;
-    create table geo.alt_src_names
-    (
-        geo_id       int
-      , alt_name     varchar
-      , lang         varchar
-      , historic     varchar
-    );"#;
+fn parse_period_value(raw: &Option<String>, bound: Bound, alternate_name_id: i64, error_count: &mut u64) -> Option<NaiveDate> {
 
-    sqlx::raw_sql(sql).execute(pool)
-        .await.map_err(|e| AppError::SqlxError(e, sql.to_string()))
+    let raw = raw.as_deref()?;
+    let date = periods::parse_period_date(raw, bound);
+    if date.is_none() {
+        *error_count += 1;
+        if *error_count <= MAX_LOGGED_PERIOD_ERRORS {
+            warn!("Unable to parse '{}' as a year or date (alternate name id {})", raw, alternate_name_id);
+        }
+    }
+    date
 }
 
 
-async fn transfer_data(pool: &Pool<Postgres>) -> Result<PgQueryResult, AppError>  {
+fn find_scattered_ids(source_file_path: &Path) -> Result<IdSet, AppError> {
 
-    let sql = r#"insert into geo.alt_names (id, alt_name, langs, historic)
-        select geo_id, alt_name,
-	    string_agg(lang, ','), historic
-        from geo.alt_src_names
-        group by geo_id, alt_name, historic
-        order by geo_id, alt_name"#;
+    // A first pass through the file, reading only the geoname ids.
 
-    sqlx::raw_sql(sql).execute(pool)
-        .await.map_err(|e| AppError::SqlxError(e, sql.to_string()))
+    let mut csv_rdr = get_csv_reader(source_file_path)?;
+    let mut check = ContiguityCheck::new();
+    let mut record = csv::StringRecord::new();
+
+    while csv_rdr.read_record(&mut record)? {
+        let geo_id: i64 = record.get(1).unwrap_or("").parse()
+            .map_err(|_| AppError::PipelineError(format!("Invalid geoname id in record {}", csv_rdr.position().record())))?;
+        check.add(geo_id);
+    }
+
+    Ok(check.scattered)
+}
+
+
+// Returns the number of batches skipped (0 or 1), those already stored by
+// an interrupted import.
+
+// Batches already stored are not sent again, so their data is not hashed.
+
+fn get_checkpoint(batch_no: i32, end_offset: u64, next_geoname_id: Option<i64>, prefix_hasher: &mut PrefixHasher,
+                  resume_point: &ResumePoint) -> std::io::Result<Checkpoint> {
+
+    let prefix_sha256 = match resume_point.is_completed(batch_no) {
+        true => None,
+        false => Some(prefix_hasher.hash_to(end_offset)?),
+    };
+    Ok(Checkpoint { batch_no, end_offset: end_offset as i64, next_geoname_id, prefix_sha256 })
 }
 
-        
\ No newline at end of file
+
+fn send_batch(tx: &mpsc::Sender<(Checkpoint, ImportBatch)>, cp: Checkpoint, dv: ImportBatch, resume_point: &ResumePoint) -> Result<u64, AppError> {
+
+    if resume_point.is_completed(cp.batch_no) {
+        return Ok(1);
+    }
+
+    // Blocks while the channel is full. An error means that every writer has
+    // stopped, in which case there is no point in reading any further.
+
+    tx.blocking_send((cp, dv))
+        .map_err(|_| AppError::PipelineError("Database writers stopped before all batches were sent".to_string()))?;
+    Ok(0)
+}
+
+
+async fn write_batches(rx: Arc<Mutex<mpsc::Receiver<(Checkpoint, ImportBatch)>>>, pool: Pool<Postgres>,
+                       load_mode: LoadMode, tables: GeoTables, source_file_name: Arc<String>) -> Result<(), AppError> {
+
+    // Each batch is written directly to the staging alt_names and other tables,
+    // in a transaction that also records its checkpoint, so that a batch is
+    // either stored completely, and known to be stored, or not at all.
+    // The loop ends when the reader has finished and the channel is empty.
+
+    loop {
+        let batch = rx.lock().await.recv().await;
+        let (cp, dv) = match batch {
+            Some(b) => b,
+            None => break,
+        };
+
+        let mut tx = pool.begin().await
+                .map_err(|e| AppError::DBPoolError("Unable to start a transaction".to_string(), e))?;
+        dv.store_data(&mut tx, &tables, load_mode).await?;
+        checkpoint::store_checkpoint(&cp, &source_file_name, &tables, &mut tx).await?;
+        tx.commit().await
+            .map_err(|e| AppError::SqlxError(e, "commit transaction".to_string()))?;
+    }
+
+    Ok(())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_v2_record_read() {
+        let record = csv::StringRecord::from(vec!["13", "498817", "en", "Leningrad", "", "", "", "1", "1924", "1991"]);
+        let source = to_alt_name(&record, SourceLayout::V2).unwrap();
+        assert_eq!(source.alternate_name_id, 13);
+        assert_eq!(source.iso_language, Some("en".to_string()));
+        assert_eq!(source.is_historic, Some(1));
+        assert_eq!(source.yfrom, Some("1924".to_string()));
+        assert_eq!(source.yto, Some("1991".to_string()));
+    }
+
+    #[test]
+    fn check_v1_record_read() {
+        let record = csv::StringRecord::from(vec!["13", "498817", "", "Leningrad", "", "", "", "1"]);
+        let source = to_alt_name(&record, SourceLayout::V1).unwrap();
+        assert_eq!(source.geoname_id, 498817);
+        assert_eq!(source.iso_language, None);
+        assert_eq!(source.alternate_name, "Leningrad");
+        assert_eq!(source.is_historic, Some(1));
+        assert_eq!(source.yfrom, None);
+        assert!(to_alt_name(&record, SourceLayout::V2).is_err());
+    }
+
+    #[test]
+    fn check_import_ends_when_writers_fail() {
+
+        // Enough geoname ids for more batches than the channel can hold.
+
+        let folder = std::env::temp_dir().join(format!("alt_names_writers_{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        let data: String = (1..=30000).map(|i| format!("{}\t{}\ten\tName {}\t\t\t\t\t\t\n", i, i, i)).collect();
+        std::fs::write(folder.join("writers.txt"), data).unwrap();
+
+        let opts = ImportOptions {
+            script_filter: ScriptFilter::all(),
+            lang_filter: LangFilter::new(&[], &[]).unwrap(),
+            load_mode: LoadMode::Copy,
+            exact: false,
+            postal_codes: true,
+            resume: false,
+            tables: GeoTables::default(),
+        };
+
+        // The pool cannot connect, so every writer fails on its first batch.
+        // The import is run on its own thread, so that a hang fails the test.
+
+        let data_folder = folder.clone();
+        let (done_tx, done_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            let result = rt.block_on(async {
+                let pool = sqlx::postgres::PgPoolOptions::new()
+                    .acquire_timeout(std::time::Duration::from_secs(1))
+                    .connect_lazy("postgres://user:pw@127.0.0.1:1/geo").unwrap();
+                import_data(&data_folder, "writers.txt", &pool, opts).await
+            });
+            let _ = done_tx.send(result.is_err());
+        });
+
+        let outcome = done_rx.recv_timeout(std::time::Duration::from_secs(30));
+        let _ = std::fs::remove_dir_all(&folder);
+        assert_eq!(outcome, Ok(true));
+    }
+}
@@ -1,29 +1,183 @@
-use sqlx::{postgres::PgQueryResult, Pool, Postgres};
+use sqlx::{postgres::PgQueryResult, Pool, Postgres, PgConnection};
 use crate::AppError;
+use crate::tables::GeoTables;
+use crate::migrations;
+use log::info;
 
-pub async fn create_geo_tables(pool: &Pool<Postgres>) -> Result<(), AppError> {
+// A full import builds its tables in the staging schema. Only when they have
+// been loaded and indexed are they swapped into the live schema, in a single
+// transaction, so that the live tables are never empty or half loaded, and
+// are left untouched if the import fails. The tables they replace are moved
+// into the backup schema, from which they can be restored with --rollback.
+// The staging and backup schemas are named after the live schema, e.g.
+// geo_staging and geo_backup, and any schema missing is created.
 
-    execute_sql(get_alt_names_sql(), pool).await?;
-   
+// The tables built by a full import, and swapped as a set, including the
+// table recording their version. The postal codes table is optional, so may
+// be absent from any of the schemas.
+
+const GEO_TABLES: [&str; 9] = ["schema_version", "alt_names", "alt_src_names", "applied_deltas", "import_checkpoints",
+                               "wikidata_xref", "links", "codes", "postal_codes"];
+
+
+// Any tables remaining in the staging schema are removed, and new ones
+// created at the latest version, using the migrations.
+
+pub async fn create_geo_tables(pool: &Pool<Postgres>, tables: &GeoTables) -> Result<(), AppError> {
+
+    let staging = tables.staging();
+    execute_sql(&format!("create schema if not exists {};", staging.schema), pool).await?;
+    for table in GEO_TABLES {
+        execute_sql(&format!("drop table if exists {};", staging.name(table)), pool).await?;
+    }
+    migrations::build_tables(pool, &staging).await
+}
+
+
+// The indexes are created once the staging tables have been loaded, which
+// is quicker than maintaining them during the load. Index names carry the
+// table prefix, as they must be unique within a schema.
+
+pub async fn create_geo_indexes(pool: &Pool<Postgres>, tables: &GeoTables, postal_codes: bool) -> Result<(), AppError> {
+
+    let staging = tables.staging();
+    migrations::build_indexes(pool, &staging).await?;
+
+    // The postal codes table is optional. If it is not wanted it is removed
+    // before the swap, so that the swap also removes any live table, rather
+    // than leaving it stale.
+
+    if !postal_codes {
+        execute_sql(&format!("drop table if exists {};", staging.name("postal_codes")), pool).await?;
+    }
+    Ok(())
+}
+
+
+// Moves the live tables into the backup schema, replacing any tables already
+// there, and the staging tables into the live schema. Moving a table takes
+// its indexes with it. Readers of the live tables are only blocked for the
+// duration of the transaction.
+
+pub async fn swap_in_staging_tables(pool: &Pool<Postgres>, tables: &GeoTables) -> Result<(), AppError> {
+
+    let (staging, backup) = (tables.staging(), tables.backup());
+    let mut tx = pool.begin().await
+                .map_err(|e| AppError::SqlxError(e, "begin transaction".to_string()))?;
+
+    execute_sql_in_tx(&format!("create schema if not exists {};", backup.schema), &mut tx).await?;
+    for table in GEO_TABLES {
+        execute_sql_in_tx(&format!("drop table if exists {};", backup.name(table)), &mut tx).await?;
+        move_table(table, tables, &backup, &mut tx).await?;
+        move_table(table, &staging, tables, &mut tx).await?;
+    }
+
+    tx.commit().await
+        .map_err(|e| AppError::SqlxError(e, "commit transaction".to_string()))?;
+
+    info!("Swapped the new tables into schema {}, with the previous tables kept in schema {}", tables.schema, backup.schema);
+    Ok(())
+}
+
+
+// Exchanges the live and backup tables, using the staging schema as a
+// temporary home for the live tables. Rolling back a second time therefore
+// restores the tables that were rolled back. After a successful swap the
+// staging schema is empty.
+
+pub async fn roll_back_tables(pool: &Pool<Postgres>, tables: &GeoTables) -> Result<(), AppError> {
+
+    let (staging, backup) = (tables.staging(), tables.backup());
+    let sql = format!("select to_regclass('{}') is not null", backup.name("alt_names"));
+    let has_backup: bool = sqlx::query_scalar(&sql).fetch_one(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    if !has_backup {
+        return Err(AppError::PipelineError(format!("There are no tables in schema {} to roll back to", backup.schema)));
+    }
+
+    // The staging schema is needed for the exchange, so rolling back is
+    // refused while it holds an interrupted import, which could be resumed.
+
+    let sql = format!("select to_regclass('{}') is not null", staging.name("import_checkpoints"));
+    let has_import: bool = sqlx::query_scalar(&sql).fetch_one(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    if has_import {
+        return Err(AppError::PipelineError(format!("Schema {} holds an interrupted import, which rolling back would destroy \
+                    - complete it with --resume, or run a full import, before rolling back", staging.schema)));
+    }
+
+    let mut tx = pool.begin().await
+                .map_err(|e| AppError::SqlxError(e, "begin transaction".to_string()))?;
+
+    execute_sql_in_tx(&format!("create schema if not exists {};", staging.schema), &mut tx).await?;
+    for table in GEO_TABLES {
+        execute_sql_in_tx(&format!("drop table if exists {};", staging.name(table)), &mut tx).await?;
+        move_table(table, tables, &staging, &mut tx).await?;
+        move_table(table, &backup, tables, &mut tx).await?;
+        move_table(table, &staging, &backup, &mut tx).await?;
+    }
+
+    tx.commit().await
+        .map_err(|e| AppError::SqlxError(e, "commit transaction".to_string()))?;
+
+    info!("Rolled back the tables in schema {} to those in schema {}, which now holds the replaced tables", tables.schema, backup.schema);
     Ok(())
 }
 
 
+async fn move_table(table: &str, from: &GeoTables, to: &GeoTables, conn: &mut PgConnection) -> Result<PgQueryResult, AppError> {
+    let sql = format!("alter table if exists {} set schema {};", from.name(table), to.schema);
+    execute_sql_in_tx(&sql, conn).await
+}
+
+
 async fn execute_sql(sql: &str, pool: &Pool<Postgres>) -> Result<PgQueryResult, AppError> {
     
-    sqlx::raw_sql(&sql).execute(pool)
+    sqlx::raw_sql(sql).execute(pool)
         .await.map_err(|e| AppError::SqlxError(e, sql.to_string()))
 }
 
 
-fn get_alt_names_sql <'a>() -> &'a str {
-    r#"drop table if exists geo.alt_names;
-    create table geo.alt_names
+async fn execute_sql_in_tx(sql: &str, conn: &mut PgConnection) -> Result<PgQueryResult, AppError> {
+
+    sqlx::raw_sql(sql).execute(conn)
+        .await.map_err(|e| AppError::SqlxError(e, sql.to_string()))
+}
+
+
+// A row for every import, recording its source file, settings, counts and
+// timings. Unlike the other tables this is kept across imports. It is
+// created, along with the live schema if need be, before the import starts.
+
+pub async fn create_import_runs_table(pool: &Pool<Postgres>, tables: &GeoTables) -> Result<(), AppError> {
+
+    execute_sql(&format!("create schema if not exists {};", tables.schema), pool).await?;
+    execute_sql(&get_import_runs_sql(tables), pool).await?;
+    Ok(())
+}
+
+
+fn get_import_runs_sql(tables: &GeoTables) -> String {
+    let table = tables.name("import_runs");
+    format!(r#"create table if not exists {table}
     (
-        id           int   
-      , alt_name 	 varchar  
-      , langs        varchar
-      , historic     varchar
-    );
-    create index alt_names_idx on geo.alt_names(id);"#
-}
\ No newline at end of file
+        run_id             int generated always as identity primary key
+      , source_file        varchar
+      , file_size          int8
+      , sha256             varchar
+      , source_modified    timestamp
+      , started_at         timestamp
+      , ended_at           timestamp
+      , status             varchar
+      , error_message      varchar
+      , records_read       int8
+      , records_kept       int8
+      , lang_excluded      int8
+      , script_excluded    int8
+      , period_parse_errors int8
+      , alt_names_rows     int8
+      , exclusions         jsonb
+      , flags              jsonb
+      , config             jsonb
+    );"#)
+}
@@ -0,0 +1,118 @@
+use crate::AppError;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+
+// Decides which language codes are imported into geo.alt_names.
+// Both lists hold glob patterns, matched without regard to case, e.g. "en",
+// "zh*" or "fr-*". Records without a language code are matched as "none".
+// If the include list is empty all codes are included, other than those
+// matching the exclude list. Exclusion takes precedence over inclusion.
+
+#[derive(Debug, Clone)]
+pub struct LangFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    include_patterns: Vec<String>,
+    exclude_patterns: Vec<String>,
+}
+
+impl LangFilter {
+
+    pub fn new(include_patterns: &[String], exclude_patterns: &[String]) -> Result<Self, AppError> {
+
+        let include = match include_patterns.is_empty() {
+            true => None,
+            false => Some(build_glob_set(include_patterns)?),
+        };
+        let exclude = build_glob_set(exclude_patterns)?;
+
+        Ok(LangFilter {
+            include,
+            exclude,
+            include_patterns: include_patterns.to_vec(),
+            exclude_patterns: exclude_patterns.to_vec(),
+        })
+    }
+
+    pub fn accepts(&self, lang_code: &str) -> bool {
+        if self.exclude.is_match(lang_code) {
+            return false;
+        }
+        match &self.include {
+            Some(include) => include.is_match(lang_code),
+            None => true,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        let include = match self.include_patterns.is_empty() {
+            true => "all".to_string(),
+            false => self.include_patterns.join(", "),
+        };
+        let exclude = match self.exclude_patterns.is_empty() {
+            true => "none".to_string(),
+            false => self.exclude_patterns.join(", "),
+        };
+        format!("include {}; exclude {}", include, exclude)
+    }
+}
+
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, AppError> {
+
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = GlobBuilder::new(pattern.trim()).case_insensitive(true).build()
+            .map_err(|e| AppError::ConfigurationError("Invalid language code pattern.".to_string(),
+                        format!("'{}' could not be parsed: {}", pattern, e)))?;
+        builder.add(glob);
+    }
+    builder.build()
+        .map_err(|e| AppError::ConfigurationError("Invalid language code patterns.".to_string(), e.to_string()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_strings(v: &[&str]) -> Vec<String> {
+        v.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn check_exclude_only() {
+        let filter = LangFilter::new(&[], &to_strings(&["link", "wkdt", "post"])).unwrap();
+        assert!(filter.accepts("en"));
+        assert!(filter.accepts("none"));
+        assert!(!filter.accepts("link"));
+        assert!(!filter.accepts("post"));
+        assert!(filter.accepts("abbr"));
+    }
+
+    #[test]
+    fn check_include_list_with_patterns() {
+        let filter = LangFilter::new(&to_strings(&["en", "fr*", "none"]), &to_strings(&["fr-1793"])).unwrap();
+        assert!(filter.accepts("en"));
+        assert!(filter.accepts("EN"));
+        assert!(filter.accepts("fr"));
+        assert!(filter.accepts("fr-CA"));
+        assert!(!filter.accepts("fr-1793"));
+        assert!(filter.accepts("none"));
+        assert!(!filter.accepts("de"));
+        assert!(!filter.accepts("link"));
+    }
+
+    #[test]
+    fn check_empty_lists_accept_everything() {
+        let filter = LangFilter::new(&[], &[]).unwrap();
+        assert!(filter.accepts("wkdt"));
+        assert!(filter.accepts("en"));
+        assert_eq!(filter.describe(), "include all; exclude none");
+    }
+
+    #[test]
+    #[should_panic]
+    fn check_invalid_pattern_panics() {
+        let _filter = LangFilter::new(&to_strings(&["en["]), &[]).unwrap();
+    }
+}
@@ -1,16 +1,33 @@
 
 pub mod setup;
 pub mod err;
+pub mod lookup;
+pub mod tables;
 mod initialise;
+mod migrations;
 mod import;
+mod delta;
+mod checkpoint;
+mod runs;
 mod export;
 mod data_vectors;
+mod id_set;
+mod langs;
+mod links;
+mod periods;
+mod source;
+mod scripts;
 
 use setup::cli_reader;
 use err::AppError;
+use data_vectors::LoadMode;
 use std::ffi::OsString;
 use std::path::PathBuf;
 use std::fs;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde_json::json;
+use log::error;
 
 pub async fn run(args: Vec<OsString>) -> Result<(), AppError> {
 
@@ -27,25 +44,109 @@ pub async fn run(args: Vec<OsString>) -> Result<(), AppError> {
     let pool = setup::get_db_pool().await?;
     let test_run = flags.test_run;
         
+    // Batches are loaded using COPY unless the UNNEST fallback is requested.
+    let load_mode = if flags.use_unnest { LoadMode::Unnest } else { LoadMode::Copy };
+    let opts = import::ImportOptions {
+        script_filter: params.script_filter.clone(),
+        lang_filter: params.lang_filter.clone(),
+        load_mode,
+        exact: flags.exact_aggregation,
+        postal_codes: params.postal_codes,
+        resume: flags.resume_import,
+        tables: params.tables.clone(),
+    };
+
+    if flags.migrate  // bring the live tables up to the program's schema version
+    {
+        migrations::migrate(&pool, &params.tables).await?;
+    }
+
     if flags.import_data   // import ror from json file and store in ror schema tables
     {
-        initialise::create_geo_tables(&pool).await?;
+        // A resumed import continues with the existing staging tables, which
+        // must have been built by the same version of the program.
+        if flags.resume_import {
+            migrations::check_version(&pool, &params.tables.staging()).await?;
+        }
+        else {
+            initialise::create_geo_tables(&pool, &params.tables).await?;
+        }
 
-        // The fourth parameter, true, makes the process include Latin names only
-        // By default it is true, but needs to be switchable to false using a command flag
-        let latin_only = !flags.include_nonlatin;
-        import::import_data(&params.data_folder, &params.source_file_name, &pool, latin_only).await?;
+        // Each import is recorded in geo.import_runs, whether or not it
+        // succeeds. The source file is hashed while it is being imported.
+
+        initialise::create_import_runs_table(&pool, &params.tables).await?;
+        let source_file_path = params.data_folder.join(&params.source_file_name);
+        let config = json!({
+            "data_folder": params.data_folder,
+            "source_file_name": params.source_file_name,
+            "script_filter": params.script_filter.describe(),
+            "lang_filter": params.lang_filter.describe(),
+            "postal_codes": params.postal_codes,
+            "db_schema": params.tables.schema,
+            "table_prefix": params.tables.prefix,
+        });
+        let run = runs::start_run(&source_file_path, &params.source_file_name, json!(flags), config, &params.tables, &pool).await?;
+
+        let stop_hash = Arc::new(AtomicBool::new(false));
+        let hash_stop_flag = stop_hash.clone();
+        let hasher = tokio::task::spawn_blocking(move || runs::hash_file(&source_file_path, &hash_stop_flag));
+
+        // The tables are loaded and indexed in the staging schema, and only
+        // swapped in if all of that succeeds.
+
+        let result = match import::import_data(&params.data_folder, &params.source_file_name, &pool, opts.clone()).await {
+            Ok(summary) => publish_tables(&pool, &params.tables, params.postal_codes).await.map(|_| summary),
+            Err(e) => Err(e),
+        };
+
+        // A failed import does not wait for the file to be hashed. Its error
+        // takes priority over any failure to record it, which is likely if
+        // the import failed because the database went away.
+
+        if result.is_err() {
+            stop_hash.store(true, Ordering::Relaxed);
+            hasher.abort();     // in case the hash has not yet started
+        }
+        let sha256 = match hasher.await {
+            Ok(Ok(hash)) => hash,
+            _ => None,
+        };
+        let recorded = runs::finish_run(&run, sha256.as_deref(), &result, &pool).await;
+        if let (Err(e), Err(_)) = (&recorded, &result) {
+            error!("Unable to record the failed import in {}: {}", params.tables.name("import_runs"), e);
+        }
+        result?;
+        recorded?;
 
         if !test_run {
             //import::summarise_import(&pool).await?;
         }
     }
 
+    if flags.roll_back  // restore the tables replaced by the last import
+    {
+        initialise::roll_back_tables(&pool, &params.tables).await?;
+    }
+
+    if flags.apply_deltas  // apply any daily delta files not yet applied
+    {
+        migrations::check_version(&pool, &params.tables).await?;
+        delta::apply_deltas(&params.data_folder, &pool, opts).await?;
+    }
+
     if flags.export_data  // write out summary data from data in smm tables
     { 
-        export::export_data(&params.output_folder, &params.source_file_name, &pool).await?;
+        migrations::check_version(&pool, &params.tables).await?;
+        export::export_data(&params.output_folder, &params.source_file_name, &params.tables, &pool).await?;
     }
 
 
      Ok(())  
 }
+
+
+async fn publish_tables(pool: &sqlx::Pool<sqlx::Postgres>, tables: &tables::GeoTables, postal_codes: bool) -> Result<(), AppError> {
+    initialise::create_geo_indexes(pool, tables, postal_codes).await?;
+    initialise::swap_in_staging_tables(pool, tables).await
+}
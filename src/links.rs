@@ -0,0 +1,75 @@
+use url::Url;
+
+// Extracts the host from a URL, as given in the Geonames 'link' records,
+// and, for Wikipedia URLs, the language edition. The language is taken from
+// the subdomain, e.g. 'fr' from fr.wikipedia.org, ignoring the 'm' of the
+// mobile site (fr.m.wikipedia.org). Hosts are returned in lower case.
+
+pub struct LinkParts {
+    pub host: Option<String>,
+    pub wiki_lang: Option<String>,
+}
+
+pub fn parse_link(link: &str) -> LinkParts {
+
+    let host = match Url::parse(link.trim()) {
+        Ok(url) => url.host_str().map(|h| h.to_lowercase()),
+        Err(_) => None,
+    };
+
+    let wiki_lang = host.as_deref().and_then(get_wiki_lang);
+
+    LinkParts {
+        host,
+        wiki_lang,
+    }
+}
+
+
+fn get_wiki_lang(host: &str) -> Option<String> {
+
+    let subdomain = host.strip_suffix(".wikipedia.org")?;
+    let subdomain = subdomain.strip_suffix(".m").unwrap_or(subdomain);
+    let subdomain = subdomain.strip_prefix("www.").unwrap_or(subdomain);
+    if subdomain.is_empty() || subdomain == "www" || subdomain.contains('.') {
+        return None;
+    }
+    Some(subdomain.to_string())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_wikipedia_links() {
+        let parts = parse_link("https://en.wikipedia.org/wiki/London");
+        assert_eq!(parts.host.as_deref(), Some("en.wikipedia.org"));
+        assert_eq!(parts.wiki_lang.as_deref(), Some("en"));
+
+        let parts = parse_link("http://zh-min-nan.wikipedia.org/wiki/Lûn-tun");
+        assert_eq!(parts.wiki_lang.as_deref(), Some("zh-min-nan"));
+
+        let parts = parse_link("https://FR.m.Wikipedia.org/wiki/Londres");
+        assert_eq!(parts.host.as_deref(), Some("fr.m.wikipedia.org"));
+        assert_eq!(parts.wiki_lang.as_deref(), Some("fr"));
+    }
+
+    #[test]
+    fn check_other_links() {
+        let parts = parse_link("https://www.london.gov.uk/about-us");
+        assert_eq!(parts.host.as_deref(), Some("www.london.gov.uk"));
+        assert_eq!(parts.wiki_lang, None);
+
+        let parts = parse_link("https://www.wikipedia.org/");
+        assert_eq!(parts.wiki_lang, None);
+    }
+
+    #[test]
+    fn check_unparseable_links() {
+        let parts = parse_link("en.wikipedia.org/wiki/London");
+        assert_eq!(parts.host, None);
+        assert_eq!(parts.wiki_lang, None);
+    }
+}
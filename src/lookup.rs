@@ -0,0 +1,30 @@
+/***************************************************************************
+ * Lookup functions, allowing other programs to use the tables created by
+ * the import. Each takes a connection pool, which can be obtained using
+ * setup::get_db_pool, and the names of the tables (GeoTables::default()
+ * for the tables in the geo schema, without a prefix).
+ ***************************************************************************/
+
+use sqlx::{Pool, Postgres};
+use crate::err::AppError;
+use crate::tables::GeoTables;
+
+// Returns the geoname id for a transport code, where the code type is one of
+// 'iata', 'icao', 'faac' or 'unlc', e.g. ("iata", "LHR"). Neither value is
+// case sensitive. A few codes are attached to more than one geoname (for
+// instance an airport and the city it serves), in which case the lowest
+// geoname id is returned. None is returned if the code is not found.
+
+pub async fn get_geoname_id_for_code(pool: &Pool<Postgres>, tables: &GeoTables, code_type: &str, code: &str) -> Result<Option<i64>, AppError> {
+
+    let sql = &format!(r#"select geoname_id::int8 from {}
+                 where code_type = $1 and code = $2
+                 order by geoname_id
+                 limit 1"#, tables.name("codes"));
+
+    sqlx::query_scalar(sql)
+        .bind(code_type.trim().to_lowercase())
+        .bind(code.trim().to_uppercase())
+        .fetch_optional(pool).await
+        .map_err(|e| AppError::SqlxError(e, sql.to_string()))
+}
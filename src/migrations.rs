@@ -0,0 +1,436 @@
+use sqlx::{postgres::PgQueryResult, Pool, Postgres, PgConnection};
+use crate::AppError;
+use crate::tables::GeoTables;
+use log::info;
+
+// The structure of the tables built by the import is defined by a list of
+// numbered migrations, applied in order, rather than by drop and create
+// statements. Each schema (live, staging and backup) records the migrations
+// applied to its tables in a schema_version table, which is swapped along
+// with the other tables. A full import builds its staging tables from all
+// the migrations, so is always at the latest version. The live tables are
+// upgraded in place, keeping their data, by running with --migrate, and the
+// program will not use tables at a different version from its own.
+//
+// Each migration has a part that creates or alters tables and a part that
+// creates indexes. When the staging tables are built the table parts are run
+// first, and the index parts only once the data has been loaded, so index
+// parts should only refer to columns that are still present at the latest
+// version. They should also use 'if not exists', as a resumed import builds
+// the staging indexes again if its earlier attempt failed after creating
+// them. As the postal codes table may be absent, migrations that alter it
+// should use 'if exists'.
+
+struct Migration {
+    version: i32,
+    description: &'static str,
+    tables_sql: fn(&GeoTables) -> String,
+    indexes_sql: fn(&GeoTables) -> String,
+}
+
+static MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "alt_names, source, delta, checkpoint, wikidata, link, code and postal code tables",
+        tables_sql: get_v1_tables_sql,
+        indexes_sql: get_v1_indexes_sql,
+    },
+];
+
+
+pub fn latest_version() -> i32 {
+    MIGRATIONS.last().map_or(0, |m| m.version)
+}
+
+
+// Returns the version of the tables in a schema, or None if there is no
+// schema_version table (0 if it is empty).
+
+pub async fn get_version(pool: &Pool<Postgres>, tables: &GeoTables) -> Result<Option<i32>, AppError> {
+
+    let sql = format!("select to_regclass('{}') is not null", tables.name("schema_version"));
+    let has_table: bool = sqlx::query_scalar(&sql).fetch_one(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    if !has_table {
+        return Ok(None);
+    }
+
+    let sql = format!("select coalesce(max(version), 0) from {}", tables.name("schema_version"));
+    let version: i32 = sqlx::query_scalar(&sql).fetch_one(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    Ok(Some(version))
+}
+
+
+pub async fn check_version(pool: &Pool<Postgres>, tables: &GeoTables) -> Result<(), AppError> {
+
+    let latest = latest_version();
+    match get_version(pool, tables).await? {
+        Some(v) if v == latest => Ok(()),
+        Some(v) if v < latest => Err(AppError::SchemaVersionError(format!("The tables in schema {} are at version {}, but this program \
+                    uses version {} - run with --migrate to upgrade them", tables.schema, v, latest))),
+        Some(v) => Err(AppError::SchemaVersionError(format!("The tables in schema {} are at version {}, which is later than this \
+                    program's version {} - a later version of the program is required", tables.schema, v, latest))),
+        None => Err(AppError::SchemaVersionError(format!("There is no {} table - run a full import, or run with --migrate \
+                    to create or adopt the tables", tables.name("schema_version")))),
+    }
+}
+
+
+// Brings the live tables up to the latest version, applying each migration
+// not yet applied in its own transaction. Tables created before migrations
+// were introduced are adopted as version 1. If there are no tables the
+// migrations create them, empty.
+
+pub async fn migrate(pool: &Pool<Postgres>, tables: &GeoTables) -> Result<(), AppError> {
+
+    sqlx::raw_sql(&format!("create schema if not exists {};", tables.schema)).execute(pool).await
+        .map_err(|e| AppError::SqlxError(e, "create schema".to_string()))?;
+
+    let version = match get_version(pool, tables).await? {
+        Some(v) => v,
+        None => {
+            let mut conn = pool.acquire().await
+                        .map_err(|e| AppError::DBPoolError("Unable to obtain a connection".to_string(), e))?;
+
+            let sql = format!("select to_regclass('{}') is not null", tables.name("alt_names"));
+            let has_tables: bool = sqlx::query_scalar(&sql).fetch_one(&mut *conn).await
+                        .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+
+            // Existing tables are only adopted if they have the version 1
+            // structure. The check is made before the schema_version table is
+            // created, so that a refusal leaves the schema as it was.
+
+            if has_tables && !has_v1_structure(tables, &mut conn).await? {
+                return Err(AppError::SchemaVersionError(format!("The existing tables in schema {} do not have the version 1 \
+                    structure, so cannot be adopted - run a full import to replace them", tables.schema)));
+            }
+            execute_sql(&get_schema_version_sql(tables), &mut conn).await?;
+            if has_tables {
+                record_version(1, "existing tables adopted", tables, &mut conn).await?;
+                info!("Adopted the existing tables in schema {} as version 1", tables.schema);
+                1
+            }
+            else {
+                0
+            }
+        },
+    };
+
+    let latest = latest_version();
+    if version > latest {
+        return Err(AppError::SchemaVersionError(format!("The tables in schema {} are at version {}, which is later than this \
+                    program's version {}", tables.schema, version, latest)));
+    }
+
+    for m in MIGRATIONS.iter().filter(|m| m.version > version) {
+        let mut tx = pool.begin().await
+                    .map_err(|e| AppError::SqlxError(e, "begin transaction".to_string()))?;
+        execute_sql(&(m.tables_sql)(tables), &mut tx).await?;
+        execute_sql(&(m.indexes_sql)(tables), &mut tx).await?;
+        record_version(m.version, m.description, tables, &mut tx).await?;
+        tx.commit().await
+            .map_err(|e| AppError::SqlxError(e, "commit transaction".to_string()))?;
+        info!("Applied migration {} to schema {}: {}", m.version, tables.schema, m.description);
+    }
+
+    info!("The tables in schema {} are at version {}", tables.schema, latest);
+    Ok(())
+}
+
+
+// Tables built before migrations were introduced may predate columns added
+// to version 1, so alt_names is checked for the last of those added.
+
+async fn has_v1_structure(tables: &GeoTables, conn: &mut PgConnection) -> Result<bool, AppError> {
+
+    let sql = r#"select count(*) from information_schema.columns
+                 where table_schema = $1 and table_name = $2
+                 and column_name in ('alternate_name_ids', 'is_historic')"#;
+    let count: i64 = sqlx::query_scalar(sql)
+        .bind(&tables.schema).bind(tables.local_name("alt_names"))
+        .fetch_one(conn).await
+        .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    Ok(count == 2)
+}
+
+
+// Creates the tables of a new, empty, schema (the staging schema of a full
+// import), at the latest version, but without their indexes.
+
+pub async fn build_tables(pool: &Pool<Postgres>, tables: &GeoTables) -> Result<(), AppError> {
+
+    let mut tx = pool.begin().await
+                .map_err(|e| AppError::SqlxError(e, "begin transaction".to_string()))?;
+    execute_sql(&get_schema_version_sql(tables), &mut tx).await?;
+    for m in MIGRATIONS {
+        execute_sql(&(m.tables_sql)(tables), &mut tx).await?;
+        record_version(m.version, m.description, tables, &mut tx).await?;
+    }
+    tx.commit().await
+        .map_err(|e| AppError::SqlxError(e, "commit transaction".to_string()))?;
+    Ok(())
+}
+
+
+pub async fn build_indexes(pool: &Pool<Postgres>, tables: &GeoTables) -> Result<(), AppError> {
+
+    let mut conn = pool.acquire().await
+                .map_err(|e| AppError::DBPoolError("Unable to obtain a connection".to_string(), e))?;
+    for m in MIGRATIONS {
+        execute_sql(&(m.indexes_sql)(tables), &mut conn).await?;
+    }
+    Ok(())
+}
+
+
+async fn record_version(version: i32, description: &str, tables: &GeoTables, conn: &mut PgConnection) -> Result<(), AppError> {
+
+    let sql = format!("insert into {} (version, description) values ($1, $2)", tables.name("schema_version"));
+    sqlx::query(&sql).bind(version).bind(description).execute(conn).await
+        .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+    Ok(())
+}
+
+
+async fn execute_sql(sql: &str, conn: &mut PgConnection) -> Result<PgQueryResult, AppError> {
+
+    sqlx::raw_sql(sql).execute(conn)
+        .await.map_err(|e| AppError::SqlxError(e, sql.to_string()))
+}
+
+
+fn get_schema_version_sql(tables: &GeoTables) -> String {
+    let table = tables.name("schema_version");
+    format!(r#"create table if not exists {table}
+    (
+        version            int primary key
+      , description        varchar
+      , applied_at         timestamp default now()
+    );"#)
+}
+
+
+// Version 1: the tables as they were when migrations were introduced.
+
+fn get_v1_tables_sql(tables: &GeoTables) -> String {
+    [
+        get_alt_names_sql(tables),
+        get_alt_src_names_sql(tables),
+        get_applied_deltas_sql(tables),
+        get_import_checkpoints_sql(tables),
+        get_wikidata_xref_sql(tables),
+        get_links_sql(tables),
+        get_codes_sql(tables),
+        get_postal_codes_sql(tables),
+    ].join("\n")
+}
+
+
+fn get_v1_indexes_sql(tables: &GeoTables) -> String {
+    [
+        get_alt_names_indexes_sql(tables),
+        get_alt_src_names_indexes_sql(tables),
+        get_wikidata_xref_indexes_sql(tables),
+        get_links_indexes_sql(tables),
+        get_codes_indexes_sql(tables),
+        get_postal_codes_indexes_sql(tables),
+    ].join("\n")
+}
+
+
+fn get_alt_names_sql(tables: &GeoTables) -> String {
+    let table = tables.name("alt_names");
+    format!(r#"create table {table}
+    (
+        id           int   
+      , alt_name 	 varchar  
+      , langs        text[]
+      , is_historic  bool
+      , valid_from   date
+      , valid_to     date
+      , from_raw     varchar
+      , to_raw       varchar
+      , script       varchar
+      , is_preferred_name  bool
+      , preferred_langs    text[]
+      , is_short_name      bool
+      , is_colloquial      bool
+      , alternate_name_ids int8[]
+    );"#)
+}
+
+
+fn get_alt_names_indexes_sql(tables: &GeoTables) -> String {
+    let table = tables.name("alt_names");
+    let prefix = &tables.prefix;
+    format!(r#"create index if not exists {prefix}alt_names_idx on {table}(id);
+    create index if not exists {prefix}alt_names_script_idx on {table}(script);
+    create index if not exists {prefix}alt_names_langs_idx on {table} using gin(langs);
+    create index if not exists {prefix}alt_names_src_ids_idx on {table} using gin(alternate_name_ids);"#)
+}
+
+
+// The source records, unfiltered and unaggregated, retained so that the
+// names of a geoname can be rebuilt when a delta changes any of its records.
+
+fn get_alt_src_names_sql(tables: &GeoTables) -> String {
+    let table = tables.name("alt_src_names");
+    format!(r#"create table {table}
+    (
+        alternate_name_id  int8
+      , geoname_id         int
+      , iso_language       varchar
+      , alternate_name     varchar
+      , is_preferred_name  bool
+      , is_short_name      bool
+      , is_colloquial      bool
+      , is_historic        bool
+      , yfrom              varchar
+      , yto                varchar
+    );"#)
+}
+
+
+fn get_alt_src_names_indexes_sql(tables: &GeoTables) -> String {
+    let table = tables.name("alt_src_names");
+    let prefix = &tables.prefix;
+    format!(r#"create index if not exists {prefix}alt_src_names_id_idx on {table}(alternate_name_id);
+    create index if not exists {prefix}alt_src_names_geoname_idx on {table}(geoname_id);"#)
+}
+
+
+// The dates of the Geonames daily delta files applied since the last full
+// import (a full import therefore starts the list again).
+
+fn get_applied_deltas_sql(tables: &GeoTables) -> String {
+    let table = tables.name("applied_deltas");
+    format!(r#"create table {table}
+    (
+        delta_date         date primary key
+      , modified_count     int
+      , deleted_count      int
+      , geoname_count      int
+      , applied_at         timestamp default now()
+    );"#)
+}
+
+
+// The batches stored so far by the current (or last) import, used to resume
+// an import that has been interrupted.
+
+fn get_import_checkpoints_sql(tables: &GeoTables) -> String {
+    let table = tables.name("import_checkpoints");
+    format!(r#"create table {table}
+    (
+        batch_no           int primary key
+      , source_file        varchar
+      , end_offset         int8
+      , next_geoname_id    int8
+      , prefix_sha256      varchar
+      , completed_at       timestamp default now()
+    );"#)
+}
+
+
+fn get_wikidata_xref_sql(tables: &GeoTables) -> String {
+    let table = tables.name("wikidata_xref");
+    format!(r#"create table {table}
+    (
+        geoname_id         int
+      , qid                varchar
+      , alternate_name_id  int8
+    );"#)
+}
+
+
+fn get_wikidata_xref_indexes_sql(tables: &GeoTables) -> String {
+    let table = tables.name("wikidata_xref");
+    let prefix = &tables.prefix;
+    format!(r#"create index if not exists {prefix}wikidata_xref_geoname_idx on {table}(geoname_id);
+    create index if not exists {prefix}wikidata_xref_qid_idx on {table}(qid);"#)
+}
+
+
+fn get_links_sql(tables: &GeoTables) -> String {
+    let table = tables.name("links");
+    format!(r#"create table {table}
+    (
+        geoname_id         int
+      , url                varchar
+      , host               varchar
+      , wiki_lang          varchar
+      , alternate_name_id  int8
+    );"#)
+}
+
+
+fn get_links_indexes_sql(tables: &GeoTables) -> String {
+    let table = tables.name("links");
+    let prefix = &tables.prefix;
+    format!(r#"create index if not exists {prefix}links_geoname_idx on {table}(geoname_id);
+    create index if not exists {prefix}links_wiki_lang_idx on {table}(wiki_lang);"#)
+}
+
+
+fn get_codes_sql(tables: &GeoTables) -> String {
+    let table = tables.name("codes");
+    format!(r#"create table {table}
+    (
+        geoname_id         int
+      , code_type          varchar
+      , code               varchar
+      , alternate_name_id  int8
+    );"#)
+}
+
+
+fn get_codes_indexes_sql(tables: &GeoTables) -> String {
+    let table = tables.name("codes");
+    let prefix = &tables.prefix;
+    format!(r#"create index if not exists {prefix}codes_idx on {table}(geoname_id, code_type, code);
+    create index if not exists {prefix}codes_lookup_idx on {table}(code_type, code);"#)
+}
+
+
+fn get_postal_codes_sql(tables: &GeoTables) -> String {
+    let table = tables.name("postal_codes");
+    format!(r#"create table {table}
+    (
+        geoname_id         int
+      , postal_code        varchar
+      , normalised_code    varchar
+      , alternate_name_id  int8
+    );"#)
+}
+
+
+fn get_postal_codes_indexes_sql(tables: &GeoTables) -> String {
+    let table = tables.name("postal_codes");
+    let prefix = &tables.prefix;
+    format!(r#"create index if not exists {prefix}postal_codes_idx on {table}(geoname_id);
+    create index if not exists {prefix}postal_codes_normalised_idx on {table}(normalised_code);"#)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_migrations_numbered_in_order() {
+        let versions: Vec<i32> = MIGRATIONS.iter().map(|m| m.version).collect();
+        let expected: Vec<i32> = (1..=MIGRATIONS.len() as i32).collect();
+        assert_eq!(versions, expected);
+        assert_eq!(latest_version(), MIGRATIONS.len() as i32);
+    }
+
+    #[test]
+    fn check_migration_sql_uses_table_names() {
+        let tables = GeoTables::new("geonames", "v2_").unwrap();
+        let sql = get_v1_tables_sql(&tables);
+        assert!(sql.contains("create table geonames.v2_alt_names"));
+        assert!(!sql.contains("drop table"));
+        assert!(get_v1_indexes_sql(&tables).contains("create index if not exists v2_codes_idx on geonames.v2_codes"));
+    }
+}
@@ -0,0 +1,122 @@
+use chrono::{Datelike, Months, NaiveDate};
+
+// Parses the 'from' and 'to' values of the Geonames alternate names file.
+// These are normally years, but may be partial or full dates. A value is
+// converted to a date at the start (for 'from') or the end (for 'to') of the
+// period it describes, so that 1917 becomes 1917-01-01 as a start date and
+// 1917-12-31 as an end date. The forms recognised are YYYY, YYYY-MM,
+// YYYY-MM-DD and YYYYMMDD, with the year optionally preceded by a '-' to
+// indicate a year BC (so -43 is 43 BC, i.e. astronomical year -42).
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bound {
+    Start,
+    End,
+}
+
+pub fn parse_period_date(raw: &str, bound: Bound) -> Option<NaiveDate> {
+
+    let raw = raw.trim();
+    let (is_bc, value) = match raw.strip_prefix('-') {
+        Some(v) => (true, v),
+        None => (false, raw),
+    };
+
+    // The YYYYMMDD form is only sliced once it is known to be all digits, as
+    // slicing other 8 byte values could split a multibyte character.
+
+    let parts: Vec<&str> = if value.len() == 8 && value.bytes().all(|b| b.is_ascii_digit()) {
+        vec![&value[0..4], &value[4..6], &value[6..8]]
+    }
+    else {
+        value.split('-').collect()
+    };
+
+    if parts.is_empty() || parts.len() > 3 || parts.iter().any(|p| p.is_empty() || !p.bytes().all(|b| b.is_ascii_digit())) {
+        return None;
+    }
+
+    let mut year: i32 = parts[0].parse().ok()?;
+    if is_bc {
+        if year == 0 {
+            return None;
+        }
+        year = 1 - year;
+    }
+    let month: Option<u32> = parts.get(1).map(|m| m.parse()).transpose().ok()?;
+    let day: Option<u32> = parts.get(2).map(|d| d.parse()).transpose().ok()?;
+
+    match (month, day, bound) {
+        (None, _, Bound::Start) => NaiveDate::from_ymd_opt(year, 1, 1),
+        (None, _, Bound::End) => NaiveDate::from_ymd_opt(year, 12, 31),
+        (Some(m), None, Bound::Start) => NaiveDate::from_ymd_opt(year, m, 1),
+        (Some(m), None, Bound::End) => NaiveDate::from_ymd_opt(year, m, 1)
+                                        .and_then(|d| d.checked_add_months(Months::new(1)))
+                                        .and_then(|d| d.pred_opt()),
+        (Some(m), Some(d), _) => NaiveDate::from_ymd_opt(year, m, d),
+    }
+}
+
+
+// Formats a date as Postgres expects it, in ISO form with any year before
+// 1 AD given as a positive year followed by 'BC'.
+
+pub fn to_pg_date_string(date: &NaiveDate) -> String {
+    if date.year() > 0 {
+        format!("{:04}-{:02}-{:02}", date.year(), date.month(), date.day())
+    }
+    else {
+        format!("{:04}-{:02}-{:02} BC", 1 - date.year(), date.month(), date.day())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ymd(y: i32, m: u32, d: u32) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(y, m, d)
+    }
+
+    #[test]
+    fn check_years_parsed_to_start_and_end() {
+        assert_eq!(parse_period_date("1917", Bound::Start), ymd(1917, 1, 1));
+        assert_eq!(parse_period_date("1917", Bound::End), ymd(1917, 12, 31));
+        assert_eq!(parse_period_date(" 43 ", Bound::Start), ymd(43, 1, 1));
+    }
+
+    #[test]
+    fn check_partial_and_full_dates_parsed() {
+        assert_eq!(parse_period_date("1924-01", Bound::Start), ymd(1924, 1, 1));
+        assert_eq!(parse_period_date("1924-02", Bound::End), ymd(1924, 2, 29));
+        assert_eq!(parse_period_date("1991-09-06", Bound::Start), ymd(1991, 9, 6));
+        assert_eq!(parse_period_date("1991-09-06", Bound::End), ymd(1991, 9, 6));
+        assert_eq!(parse_period_date("19910906", Bound::End), ymd(1991, 9, 6));
+    }
+
+    #[test]
+    fn check_bc_years_parsed() {
+        assert_eq!(parse_period_date("-43", Bound::Start), ymd(-42, 1, 1));
+        assert_eq!(parse_period_date("-43", Bound::End), ymd(-42, 12, 31));
+    }
+
+    #[test]
+    fn check_invalid_values_rejected() {
+        assert_eq!(parse_period_date("", Bound::Start), None);
+        assert_eq!(parse_period_date("c. 1900", Bound::Start), None);
+        assert_eq!(parse_period_date("19th century", Bound::Start), None);
+        assert_eq!(parse_period_date("1924-13", Bound::Start), None);
+        assert_eq!(parse_period_date("1924-02-30", Bound::End), None);
+        assert_eq!(parse_period_date("1924--02", Bound::End), None);
+        assert_eq!(parse_period_date("-0", Bound::End), None);
+        assert_eq!(parse_period_date("19年123", Bound::Start), None);
+    }
+
+    #[test]
+    fn check_pg_date_strings() {
+        assert_eq!(to_pg_date_string(&ymd(1917, 12, 31).unwrap()), "1917-12-31");
+        assert_eq!(to_pg_date_string(&ymd(43, 1, 1).unwrap()), "0043-01-01");
+        assert_eq!(to_pg_date_string(&ymd(-42, 1, 1).unwrap()), "0043-01-01 BC");
+    }
+}
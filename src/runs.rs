@@ -0,0 +1,175 @@
+use sqlx::{Pool, Postgres};
+use crate::AppError;
+use crate::import::ImportSummary;
+use crate::source;
+use crate::tables::GeoTables;
+use chrono::{Local, NaiveDateTime};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use log::info;
+
+// Records each import in geo.import_runs (or the equivalent table for the
+// schema and prefix configured). A row is added as the import starts, with
+// the source file's name, size and modification date and the flags and
+// configuration used, and is completed when the import finishes, with the
+// file's SHA-256 (calculated while the import runs), the record counts and
+// the end time - or, if the import failed, the error. The same summary is
+// written to the log.
+
+pub struct ImportRun {
+    pub run_id: i32,
+    pub tables: GeoTables,
+    pub source_file: String,
+    pub file_size: i64,
+    pub source_modified: Option<NaiveDateTime>,
+    pub started_at: NaiveDateTime,
+}
+
+
+pub async fn start_run(source_file_path: &Path, source_file_name: &str, flags: Value, config: Value,
+                       tables: &GeoTables, pool: &Pool<Postgres>) -> Result<ImportRun, AppError> {
+
+    let metadata = std::fs::metadata(source_file_path)
+                .map_err(|e| AppError::IoReadErrorWithPath(e, source_file_path.to_owned()))?;
+    let file_size = metadata.len() as i64;
+    let source_modified = source::get_source_modified(source_file_path)?;
+    let started_at = Local::now().naive_local();
+
+    let sql = &format!(r#"insert into {} (source_file, file_size, source_modified, started_at, status, flags, config)
+                 values ($1, $2, $3, $4, 'running', $5::jsonb, $6::jsonb)
+                 returning run_id"#, tables.name("import_runs"));
+    let run_id: i32 = sqlx::query_scalar(sql)
+        .bind(source_file_name).bind(file_size).bind(source_modified).bind(started_at)
+        .bind(flags.to_string()).bind(config.to_string())
+        .fetch_one(pool).await
+        .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+
+    Ok(ImportRun {
+        run_id,
+        tables: tables.clone(),
+        source_file: source_file_name.to_string(),
+        file_size,
+        source_modified,
+        started_at,
+    })
+}
+
+
+pub async fn finish_run(run: &ImportRun, sha256: Option<&str>, result: &Result<ImportSummary, AppError>,
+                        pool: &Pool<Postgres>) -> Result<(), AppError> {
+
+    let ended_at = Local::now().naive_local();
+
+    let summary = match result {
+        Ok(summary) => summary,
+        Err(e) => {
+            let sql = &format!(r#"update {} set sha256 = $2, ended_at = $3, status = 'failed', error_message = $4
+                         where run_id = $1"#, run.tables.name("import_runs"));
+            sqlx::query(sql)
+                .bind(run.run_id).bind(sha256).bind(ended_at).bind(e.to_string())
+                .execute(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+            info!("Import run {} of {} failed", run.run_id, run.source_file);
+            return Ok(());
+        },
+    };
+
+    let sql = &format!("select count(*) from {}", run.tables.name("alt_names"));
+    let alt_names_rows: i64 = sqlx::query_scalar(sql).fetch_one(pool).await
+                .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+
+    let sql = &format!(r#"update {} set sha256 = $2, ended_at = $3, status = 'completed',
+                 records_read = $4, records_kept = $5, lang_excluded = $6, script_excluded = $7,
+                 period_parse_errors = $8, alt_names_rows = $9, exclusions = $10::jsonb
+                 where run_id = $1"#, run.tables.name("import_runs"));
+    sqlx::query(sql)
+        .bind(run.run_id).bind(sha256).bind(ended_at)
+        .bind(summary.records_read as i64).bind(summary.records_kept as i64)
+        .bind(summary.lang_drop_count() as i64).bind(summary.script_drop_count() as i64)
+        .bind(summary.period_errors as i64).bind(alt_names_rows)
+        .bind(get_exclusions_json(summary).to_string())
+        .execute(pool).await
+        .map_err(|e| AppError::SqlxError(e, sql.to_string()))?;
+
+    info!("Import run {} summary:", run.run_id);
+    info!("  source file: {} ({} bytes, modified {})", run.source_file, run.file_size,
+            run.source_modified.map_or("unknown".to_string(), |m| m.to_string()));
+    info!("  sha256: {}", sha256.unwrap_or("not calculated"));
+    info!("  started {}, ended {} ({} seconds)", run.started_at.format("%Y-%m-%d %H:%M:%S"),
+            ended_at.format("%Y-%m-%d %H:%M:%S"), (ended_at - run.started_at).num_seconds());
+    info!("  records read: {}, kept: {}", summary.records_read, summary.records_kept);
+    info!("  excluded by language code: {}, by script: {}", summary.lang_drop_count(), summary.script_drop_count());
+    info!("  unparseable from / to values: {}", summary.period_errors);
+    info!("  rows in {}: {}", run.tables.name("alt_names"), alt_names_rows);
+    Ok(())
+}
+
+
+// Calculated on a blocking thread, alongside the import. The hash is of the
+// file as it is stored, so of the archive itself for a zipped source. As a
+// blocking task cannot be aborted once it has started, it checks the stop
+// flag, set if the import fails, between reads, and then returns None.
+
+pub fn hash_file(source_file_path: &Path, stop: &AtomicBool) -> Result<Option<String>, AppError> {
+
+    let io_error = |e: std::io::Error| AppError::IoReadErrorWithPath(e, source_file_path.to_owned());
+    let mut file = File::open(source_file_path).map_err(io_error)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 1 << 20];
+    loop {
+        if stop.load(Ordering::Relaxed) {
+            return Ok(None);
+        }
+        let n = file.read(&mut buf).map_err(io_error)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(Some(format!("{:x}", hasher.finalize())))
+}
+
+
+// The records excluded for each language code and script.
+
+fn get_exclusions_json(summary: &ImportSummary) -> Value {
+    json!({
+        "lang_codes": summary.lang_drops,
+        "scripts": summary.script_drops,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn check_exclusions_json() {
+        let summary = ImportSummary {
+            records_read: 10,
+            records_kept: 6,
+            lang_drops: HashMap::from([("link".to_string(), 3)]),
+            script_drops: HashMap::from([("Cyrillic", 1)]),
+            period_errors: 0,
+        };
+        assert_eq!(get_exclusions_json(&summary), json!({"lang_codes": {"link": 3}, "scripts": {"Cyrillic": 1}}));
+        assert_eq!(summary.lang_drop_count() + summary.script_drop_count(), 4);
+    }
+
+    #[test]
+    fn check_hash_stops_when_asked() {
+        let path = std::env::temp_dir().join(format!("alt_names_hash_{}.txt", std::process::id()));
+        std::fs::write(&path, "abc").unwrap();
+        let hash = hash_file(&path, &AtomicBool::new(false)).unwrap();
+        let stopped = hash_file(&path, &AtomicBool::new(true)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(hash.as_deref(), Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"));
+        assert_eq!(stopped, None);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::AppError;
+use unicode_script::{Script, UnicodeScript};
+
+// Classifies names by the Unicode scripts of their characters.
+// Characters in the Common script (digits, punctuation, spaces, most
+// symbols) and the Inherited script (combining marks, such as a separately
+// encoded acute accent) take on the script of the text around them, so they
+// are ignored. Unassigned characters (Unknown) are ignored as well.
+
+// Returns the distinct scripts used in a name, in the order in which they
+// first appear. A name consisting only of digits and / or punctuation
+// therefore returns an empty list.
+
+pub fn get_scripts(name: &str) -> Vec<Script> {
+    let mut scripts: Vec<Script> = Vec::new();
+    for c in name.chars() {
+        let script = c.script();
+        if is_significant(script) && !scripts.contains(&script) {
+            scripts.push(script);
+        }
+    }
+    scripts
+}
+
+
+// A name counts as Latin if every character is Latin, Common or Inherited,
+// i.e. if it contains no character from any other script. Names without any
+// letters (e.g. a name made up of digits alone) are treated as Latin.
+
+#[cfg(test)]
+pub fn is_latin(name: &str) -> bool {
+    get_scripts(name).iter().all(|s| *s == Script::Latin)
+}
+
+
+// Returns the ISO 15924 code for the scripts of a name, as stored in the
+// geo.alt_names script column. A name in several scripts has each of their
+// codes, in order of appearance, joined by '+' (e.g. "Latn+Cyrl"). A name
+// without any script specific characters is given "Zyyy", the code for
+// the Common script.
+
+pub fn get_script_code(scripts: &[Script]) -> String {
+    if scripts.is_empty() {
+        return Script::Common.short_name().to_string();
+    }
+    scripts.iter().map(|s| s.short_name()).collect::<Vec<&str>>().join("+")
+}
+
+
+fn is_significant(script: Script) -> bool {
+    !matches!(script, Script::Common | Script::Inherited | Script::Unknown)
+}
+
+
+// The scripts allowed in the imported names. A name is accepted if all of its
+// scripts are in the allowed list (so, as with is_latin, names made up of
+// digits and punctuation alone are always accepted). A filter with no list
+// accepts every name.
+
+#[derive(Debug, Clone)]
+pub struct ScriptFilter {
+    allowed: Option<Vec<Script>>,
+}
+
+impl ScriptFilter {
+    pub fn all() -> Self {
+        ScriptFilter { allowed: None }
+    }
+
+    pub fn latin_only() -> Self {
+        ScriptFilter { allowed: Some(vec![Script::Latin]) }
+    }
+
+    // Builds the filter from a list of script names, as given in the config
+    // file or on the command line. Either the full Unicode name ("Cyrillic")
+    // or the ISO 15924 code ("Cyrl") can be used, in any case. A list
+    // including "all" or "*" accepts every script.
+
+    pub fn from_names(names: &[String]) -> Result<Self, AppError> {
+        let mut allowed: Vec<Script> = Vec::new();
+        for name in names {
+            let name = name.trim();
+            if name.eq_ignore_ascii_case("all") || name == "*" {
+                return Ok(ScriptFilter::all());
+            }
+            match parse_script_name(name) {
+                Some(script) => {
+                    if !allowed.contains(&script) {
+                        allowed.push(script);
+                    }
+                },
+                None => return Err(AppError::ConfigurationError("Unrecognised script name.".to_string(),
+                            format!("'{}' is not a Unicode script name or ISO 15924 code.", name))),
+            }
+        }
+        Ok(ScriptFilter { allowed: Some(allowed) })
+    }
+
+    // Returns the first script in the name that is not allowed, if any.
+
+    #[cfg(test)]
+    pub fn rejected_script(&self, name: &str) -> Option<Script> {
+        self.rejected_from(&get_scripts(name))
+    }
+
+    // As above, but given the scripts already found in the name.
+
+    pub fn rejected_from(&self, scripts: &[Script]) -> Option<Script> {
+        match &self.allowed {
+            None => None,
+            Some(allowed) => scripts.iter().find(|s| !allowed.contains(s)).copied(),
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match &self.allowed {
+            None => "all".to_string(),
+            Some(allowed) => allowed.iter().map(|s| s.full_name()).collect::<Vec<&str>>().join(", "),
+        }
+    }
+}
+
+
+fn parse_script_name(name: &str) -> Option<Script> {
+
+    // The unicode_script lookups are case sensitive, so the name is also
+    // tried in title case (e.g. "cyrillic" as "Cyrillic", "CYRL" as "Cyrl").
+
+    let mut title_case = name.to_lowercase();
+    if let Some(first) = title_case.get(0..1) {
+        title_case = first.to_uppercase() + &title_case[1..];
+    }
+
+    Script::from_full_name(name)
+        .or_else(|| Script::from_short_name(name))
+        .or_else(|| Script::from_full_name(&title_case))
+        .or_else(|| Script::from_short_name(&title_case))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_accented_names_are_latin() {
+        assert!(is_latin("Ørsted"));
+        assert!(is_latin("Åland"));
+        assert!(is_latin("København"));
+        assert!(is_latin("Żółkiewka"));
+        assert!(is_latin("Ville-d'Avray"));
+        assert!(is_latin("Saint-E\u{301}tienne"));  // combining acute accent
+        assert!(is_latin("Đà Nẵng"));
+    }
+
+    #[test]
+    fn check_non_latin_names_are_not_latin() {
+        assert!(!is_latin("Москва"));
+        assert!(!is_latin("Αθήνα"));
+        assert!(!is_latin("الرياض"));
+        assert!(!is_latin("東京"));
+        assert!(!is_latin("서울"));
+    }
+
+    #[test]
+    fn check_mixed_script_names_are_not_latin() {
+        assert!(!is_latin("Moscow Москва"));
+        assert!(!is_latin("Tokyo 東京"));
+        assert_eq!(get_scripts("Moscow Москва"), vec![Script::Latin, Script::Cyrillic]);
+        assert_eq!(get_scripts("とうきょう 東京"), vec![Script::Hiragana, Script::Han]);
+    }
+
+    #[test]
+    fn check_names_with_digits_judged_by_their_letters() {
+        assert!(is_latin("1050 Copenhagen"));
+        assert!(is_latin("10th Arrondissement"));
+        assert!(!is_latin("1050 Москва"));
+        assert!(!is_latin("(北京)"));
+        assert!(is_latin("12345"));
+        assert!(is_latin("-"));
+        assert!(get_scripts("12345").is_empty());
+    }
+
+    #[test]
+    fn check_script_codes() {
+        assert_eq!(get_script_code(&get_scripts("København")), "Latn");
+        assert_eq!(get_script_code(&get_scripts("Москва")), "Cyrl");
+        assert_eq!(get_script_code(&get_scripts("東京")), "Hani");
+        assert_eq!(get_script_code(&get_scripts("الرياض")), "Arab");
+        assert_eq!(get_script_code(&get_scripts("Moscow Москва")), "Latn+Cyrl");
+        assert_eq!(get_script_code(&get_scripts("1050")), "Zyyy");
+    }
+
+    #[test]
+    fn check_script_filter_from_names() {
+        let names = vec!["Latin".to_string(), "cyrl".to_string(), "GREEK".to_string()];
+        let filter = ScriptFilter::from_names(&names).unwrap();
+        assert_eq!(filter.describe(), "Latin, Cyrillic, Greek");
+        assert_eq!(filter.rejected_script("Москва"), None);
+        assert_eq!(filter.rejected_script("Αθήνα"), None);
+        assert_eq!(filter.rejected_script("Øresund"), None);
+        assert_eq!(filter.rejected_script("東京"), Some(Script::Han));
+        assert_eq!(filter.rejected_script("Tokyo 東京"), Some(Script::Han));
+        assert_eq!(filter.rejected_script("123"), None);
+    }
+
+    #[test]
+    fn check_script_filter_all() {
+        let names = vec!["Latin".to_string(), "all".to_string()];
+        let filter = ScriptFilter::from_names(&names).unwrap();
+        assert_eq!(filter.describe(), "all");
+        assert_eq!(filter.rejected_script("東京"), None);
+        assert_eq!(ScriptFilter::all().rejected_script("الرياض"), None);
+        assert_eq!(ScriptFilter::latin_only().rejected_script("الرياض"), Some(Script::Arabic));
+    }
+
+    #[test]
+    #[should_panic]
+    fn check_unknown_script_name_panics() {
+        let names = vec!["Latin".to_string(), "Klingon".to_string()];
+        let _filter = ScriptFilter::from_names(&names).unwrap();
+    }
+
+    #[test]
+    fn check_scripts_listed_once_in_order_found() {
+        assert_eq!(get_scripts("København"), vec![Script::Latin]);
+        assert_eq!(get_scripts("Αθήνα / Athens / Αθήνα"), vec![Script::Greek, Script::Latin]);
+        assert_eq!(get_scripts("الرياض"), vec![Script::Arabic]);
+    }
+}
@@ -8,13 +8,20 @@ use std::ffi::OsString;
 
 pub struct CliPars {
     pub source_file: String,
+    pub scripts: Vec<String>,
     pub flags: Flags, 
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, serde::Serialize)]
 pub struct Flags {
     pub import_data: bool,
     pub include_nonlatin: bool,
+    pub use_unnest: bool,
+    pub exact_aggregation: bool,
+    pub apply_deltas: bool,
+    pub resume_import: bool,
+    pub roll_back: bool,
+    pub migrate: bool,
     pub export_data: bool,
     pub test_run: bool,
 }
@@ -23,11 +30,25 @@ pub fn fetch_valid_arguments(args: Vec<OsString>) -> Result<CliPars, AppError>
 { 
     let parse_result = parse_args(args)?;
     let source_file = parse_result.get_one::<String>("src_file").unwrap();
+
+    // Scripts are given as a comma separated list, e.g. "Latin,Cyrillic,Greek".
+
+    let scripts_string = parse_result.get_one::<String>("scripts").unwrap();
+    let scripts: Vec<String> = scripts_string.split(',')
+                    .map(|s| s.trim().to_string())
+                    .filter(|s| !s.is_empty())
+                    .collect();
   
     // Flag values are false if not present, true if present.
 
     let mut r_flag = parse_result.get_flag("r_flag");
     let n_flag = parse_result.get_flag("n_flag");
+    let u_flag = parse_result.get_flag("u_flag");
+    let e_flag = parse_result.get_flag("e_flag");
+    let d_flag = parse_result.get_flag("d_flag");
+    let resume_flag = parse_result.get_flag("resume_flag");
+    let rollback_flag = parse_result.get_flag("rollback_flag");
+    let migrate_flag = parse_result.get_flag("migrate_flag");
     let mut x_flag = parse_result.get_flag("x_flag");
     let z_flag = parse_result.get_flag("z_flag");
      
@@ -35,20 +56,31 @@ pub fn fetch_valid_arguments(args: Vec<OsString>) -> Result<CliPars, AppError>
         x_flag = false;  // import is the default
     }
 
-    if !r_flag && !x_flag {
+    if !r_flag && !x_flag && !d_flag && !rollback_flag && !migrate_flag {
         r_flag = true;  // import is the default
     }
 
+    if resume_flag {
+        r_flag = true;  // resuming is a form of import
+    }
+
     let flags = Flags {
         import_data: r_flag,
         include_nonlatin: n_flag,
+        use_unnest: u_flag,
+        exact_aggregation: e_flag,
+        apply_deltas: d_flag,
+        resume_import: resume_flag,
+        roll_back: rollback_flag,
+        migrate: migrate_flag,
         export_data: x_flag,
         test_run: z_flag,
     };
 
     Ok(CliPars {
         source_file: source_file.clone(),
-        flags: flags,
+        scripts,
+        flags,
     })
 
 }
@@ -79,7 +111,59 @@ fn parse_args(args: Vec<OsString>) -> Result<ArgMatches, clap::Error> {
            .short('n')
            .long("non_latin")
            .required(false)
-           .help("A flag signifying that non Latin names shopuld be included (are excludede by dfefault)")
+           .help("A flag signifying that names in all scripts should be included (only Latin names are included by default)")
+           .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+             Arg::new("scripts")
+            .short('c')
+            .long("scripts")
+            .help("A comma separated list of the scripts to be included, e.g. Latin,Cyrillic,Greek (over-rides config setting)")
+            .default_value("")
+        )
+        .arg(
+            Arg::new("u_flag")
+           .short('u')
+           .long("unnest")
+           .required(false)
+           .help("A flag signifying that batches should be loaded using UNNEST inserts rather than COPY (for comparison)")
+           .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("e_flag")
+           .short('e')
+           .long("exact")
+           .required(false)
+           .help("A flag signifying that names should be aggregated exactly, even if a geoname's records are not contiguous in the source file")
+           .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("d_flag")
+           .short('d')
+           .long("delta")
+           .required(false)
+           .help("A flag signifying that the daily delta files in the data folder should be applied to the existing tables")
+           .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("resume_flag")
+           .long("resume")
+           .required(false)
+           .help("A flag signifying that an interrupted import should be continued from its last checkpoint")
+           .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("rollback_flag")
+           .long("rollback")
+           .required(false)
+           .help("A flag signifying that the tables replaced by the last import should be restored from the backup schema")
+           .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("migrate_flag")
+           .long("migrate")
+           .required(false)
+           .help("A flag signifying that the tables should be upgraded to the program's schema version")
            .action(clap::ArgAction::SetTrue)
         )
        .arg(
@@ -116,6 +200,7 @@ mod tests {
         let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
         let res = fetch_valid_arguments(test_args).unwrap();
         assert_eq!(res.source_file, "".to_string());
+        assert!(res.scripts.is_empty());
         assert_eq!(res.flags.import_data, true);
         assert_eq!(res.flags.export_data, false);
         assert_eq!(res.flags.test_run, false);
@@ -162,6 +247,106 @@ mod tests {
         assert_eq!(res.flags.test_run, false);
     }
 
+    #[test]
+    fn check_cli_with_u_flag() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-u"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.source_file, "".to_string());
+        assert_eq!(res.flags.import_data, true);
+        assert_eq!(res.flags.use_unnest, true);
+        assert_eq!(res.flags.export_data, false);
+        assert_eq!(res.flags.test_run, false);
+    }
+
+    #[test]
+    fn check_cli_with_e_flag() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-e"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.source_file, "".to_string());
+        assert_eq!(res.flags.import_data, true);
+        assert_eq!(res.flags.exact_aggregation, true);
+        assert_eq!(res.flags.use_unnest, false);
+        assert_eq!(res.flags.export_data, false);
+    }
+
+    #[test]
+    fn check_cli_with_d_flag() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-d"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.flags.import_data, false);
+        assert_eq!(res.flags.apply_deltas, true);
+        assert_eq!(res.flags.export_data, false);
+    }
+
+    #[test]
+    fn check_cli_with_r_and_d_flags() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-r", "-d"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.flags.import_data, true);
+        assert_eq!(res.flags.apply_deltas, true);
+        assert_eq!(res.flags.export_data, false);
+    }
+
+    #[test]
+    fn check_cli_with_resume_flag() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-x", "--resume"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.flags.import_data, true);
+        assert_eq!(res.flags.resume_import, true);
+        assert_eq!(res.flags.export_data, true);
+    }
+
+    #[test]
+    fn check_cli_with_rollback_flag() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "--rollback"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.flags.import_data, false);
+        assert_eq!(res.flags.roll_back, true);
+        assert_eq!(res.flags.export_data, false);
+    }
+
+    #[test]
+    fn check_cli_with_migrate_and_x_flags() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "--migrate", "-x"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.flags.import_data, false);
+        assert_eq!(res.flags.migrate, true);
+        assert_eq!(res.flags.export_data, true);
+    }
+
+    #[test]
+    fn check_cli_with_scripts_list() {
+        let target = "dummy target";
+        let args : Vec<&str> = vec![target, "-c", "Latin, Cyrl,Greek"];
+        let test_args = args.iter().map(|x| x.to_string().into()).collect::<Vec<OsString>>();
+
+        let res = fetch_valid_arguments(test_args).unwrap();
+        assert_eq!(res.scripts, vec!["Latin", "Cyrl", "Greek"]);
+        assert_eq!(res.flags.import_data, true);
+        assert_eq!(res.flags.include_nonlatin, false);
+    }
+
     #[test]
     fn check_cli_with_z_flags() {
         let target = "dummy target";
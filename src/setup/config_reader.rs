@@ -12,6 +12,7 @@ use std::path::PathBuf;
 pub struct TomlConfig {
     pub files: Option<TomlFilePars>, 
     pub database: Option<TomlDBPars>,
+    pub import: Option<TomlImportPars>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -22,6 +23,14 @@ pub struct TomlFilePars {
     pub src_file_name: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct TomlImportPars {
+    pub scripts: Option<Vec<String>>,
+    pub include_langs: Option<Vec<String>>,
+    pub exclude_langs: Option<Vec<String>>,
+    pub postal_codes: Option<bool>,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct TomlDBPars {
     pub db_host: Option<String>,
@@ -29,11 +38,14 @@ pub struct TomlDBPars {
     pub db_password: Option<String>,
     pub db_port: Option<String>,
     pub db_name: Option<String>,
+    pub db_schema: Option<String>,
+    pub table_prefix: Option<String>,
 }
 
 pub struct Config {
     pub files: FilePars, 
     pub db_pars: DBPars,
+    pub import_pars: ImportPars,
 }
 
 pub struct FilePars {
@@ -43,6 +55,24 @@ pub struct FilePars {
     pub src_file_name: String,
 }
 
+// The [import] section is optional. An empty list of scripts means that
+// none have been specified in the config file. An empty include_langs list
+// means all language codes are included, other than those in exclude_langs.
+// If exclude_langs is absent it defaults to the Geonames pseudo-language codes.
+// postal_codes defaults to true, i.e. the geo.postal_codes table is populated.
+
+pub struct ImportPars {
+    pub scripts: Vec<String>,
+    pub include_langs: Vec<String>,
+    pub exclude_langs: Vec<String>,
+    pub postal_codes: bool,
+}
+
+pub const DEFAULT_EXCLUDED_LANGS: [&str; 8] = ["link", "wkdt", "post", "unlc", "faac", "icao", "iata", "abbr"];
+
+// db_schema (default 'geo') and table_prefix (default none) give the names
+// of the tables, and are checked when the parameters are established.
+
 #[derive(Debug, Clone)]
 pub struct DBPars {
     pub db_host: String,
@@ -50,6 +80,8 @@ pub struct DBPars {
     pub db_password: String,
     pub db_port: usize,
     pub db_name: String,
+    pub db_schema: String,
+    pub table_prefix: String,
 }
 
 pub static DB_PARS: OnceLock<DBPars> = OnceLock::new();
@@ -74,12 +106,14 @@ pub fn populate_config_vars(config_string: &String) -> Result<Config, AppError>
    
     let config_files = verify_file_parameters(toml_files)?;
     let config_db_pars = verify_db_parameters(toml_database)?;
+    let config_import_pars = verify_import_parameters(toml_config.import);
 
     let _ = DB_PARS.set(config_db_pars.clone());
 
     Ok(Config{
         files: config_files,
         db_pars: config_db_pars,
+        import_pars: config_import_pars,
     })
 }
 
@@ -122,16 +156,45 @@ fn verify_db_parameters(toml_database: TomlDBPars) -> Result<DBPars, AppError> {
 
     let db_name = check_defaulted_string (toml_database.db_name, "DB name", "geo", "geo");
 
+    let db_schema = check_defaulted_string (toml_database.db_schema, "DB schema", "geo", "geo");
+
+    let table_prefix = toml_database.table_prefix.unwrap_or_default();
+
     Ok(DBPars {
         db_host,
         db_user,
         db_password,
         db_port,
         db_name,
+        db_schema,
+        table_prefix,
     })
 }
 
 
+fn verify_import_parameters(toml_import: Option<TomlImportPars>) -> ImportPars {
+
+    let (scripts, include_langs, exclude_langs, postal_codes) = match toml_import {
+        Some(i) => (i.scripts.unwrap_or_default(), i.include_langs.unwrap_or_default(), i.exclude_langs, i.postal_codes.unwrap_or(true)),
+        None => (Vec::new(), Vec::new(), None, true),
+    };
+
+    let exclude_langs = exclude_langs.unwrap_or_else(|| DEFAULT_EXCLUDED_LANGS.iter().map(|s| s.to_string()).collect());
+
+    ImportPars {
+        scripts: remove_blanks(scripts),
+        include_langs: remove_blanks(include_langs),
+        exclude_langs: remove_blanks(exclude_langs),
+        postal_codes,
+    }
+}
+
+
+fn remove_blanks(values: Vec<String>) -> Vec<String> {
+    values.into_iter().filter(|s| !s.trim().is_empty()).collect()
+}
+
+
 fn check_essential_string (src_name: Option<String>, value_name: &str, config_name: &str) -> Result<String, AppError> {
  
     let s = match src_name {
@@ -383,6 +446,117 @@ db_password="password"
     }
 
 
+    #[test]
+    fn check_import_scripts_read_correctly() {
+
+        let config = r#"
+[files]
+data_folder_path="E:\\MDR source data\\Geonames\\data"
+src_file_name="alternateNamesV2.txt"
+
+[database]
+db_user="user_name"
+db_password="password"
+
+[import]
+scripts=["Latin", "Cyrillic", "Grek"]
+"#;
+        let config_string = config.to_string();
+        let res = populate_config_vars(&config_string).unwrap();
+        assert_eq!(res.import_pars.scripts, vec!["Latin", "Cyrillic", "Grek"]);
+        assert!(res.import_pars.include_langs.is_empty());
+        assert_eq!(res.import_pars.exclude_langs, DEFAULT_EXCLUDED_LANGS);
+    }
+
+
+    #[test]
+    fn check_import_lang_lists_read_correctly() {
+
+        let config = r#"
+[files]
+data_folder_path="E:\\MDR source data\\Geonames\\data"
+src_file_name="alternateNamesV2.txt"
+
+[database]
+db_user="user_name"
+db_password="password"
+
+[import]
+include_langs=["en", "fr*", "none", "abbr"]
+exclude_langs=[]
+"#;
+        let config_string = config.to_string();
+        let res = populate_config_vars(&config_string).unwrap();
+        assert!(res.import_pars.scripts.is_empty());
+        assert_eq!(res.import_pars.include_langs, vec!["en", "fr*", "none", "abbr"]);
+        assert!(res.import_pars.exclude_langs.is_empty());
+        assert!(res.import_pars.postal_codes);
+    }
+
+
+    #[test]
+    fn check_postal_codes_can_be_skipped() {
+
+        let config = r#"
+[files]
+data_folder_path="E:\\MDR source data\\Geonames\\data"
+src_file_name="alternateNamesV2.txt"
+
+[database]
+db_user="user_name"
+db_password="password"
+
+[import]
+postal_codes=false
+"#;
+        let config_string = config.to_string();
+        let res = populate_config_vars(&config_string).unwrap();
+        assert!(!res.import_pars.postal_codes);
+        assert_eq!(res.import_pars.exclude_langs, DEFAULT_EXCLUDED_LANGS);
+    }
+
+
+    #[test]
+    fn check_schema_and_table_prefix_read() {
+
+        let config = r#"
+[files]
+data_folder_path="E:\\MDR source data\\Geonames\\data"
+src_file_name="alternateNamesV2.txt"
+
+[database]
+db_user="user_name"
+db_password="password"
+db_schema="geonames"
+table_prefix="v2025_"
+"#;
+        let config_string = config.to_string();
+        let res = populate_config_vars(&config_string).unwrap();
+        assert_eq!(res.db_pars.db_schema, "geonames");
+        assert_eq!(res.db_pars.table_prefix, "v2025_");
+    }
+
+
+    #[test]
+    fn check_missing_import_section_gives_empty_scripts() {
+
+        let config = r#"
+[files]
+data_folder_path="E:\\MDR source data\\Geonames\\data"
+src_file_name="alternateNamesV2.txt"
+
+[database]
+db_user="user_name"
+db_password="password"
+"#;
+        let config_string = config.to_string();
+        let res = populate_config_vars(&config_string).unwrap();
+        assert!(res.import_pars.scripts.is_empty());
+        assert!(res.import_pars.include_langs.is_empty());
+        assert_eq!(res.import_pars.exclude_langs, DEFAULT_EXCLUDED_LANGS);
+    }
+
+
 #[test]
     fn missing_port_gets_default() {
 
@@ -76,7 +76,16 @@ pub fn log_startup_params (ip : &InitParams) {
     info!("output_folder: {}", ip.output_folder.display());
     info!("source_file_name: {}", ip.source_file_name);
     info!("import_data: {}", ip.flags.import_data);
-    info!("include non Latin: {}", ip.flags.include_nonlatin);
+    info!("scripts included: {}", ip.script_filter.describe());
+    info!("language codes: {}", ip.lang_filter.describe());
+    info!("postal codes table: {}", ip.postal_codes);
+    info!("schema: {}, table prefix: '{}'", ip.tables.schema, ip.tables.prefix);
+    info!("load using UNNEST: {}", ip.flags.use_unnest);
+    info!("exact aggregation: {}", ip.flags.exact_aggregation);
+    info!("apply deltas: {}", ip.flags.apply_deltas);
+    info!("resume import: {}", ip.flags.resume_import);
+    info!("roll back: {}", ip.flags.roll_back);
+    info!("migrate: {}", ip.flags.migrate);
     info!("export_data: {}", ip.flags.export_data);
     info!("");
     info!("************************************");
@@ -22,6 +22,9 @@ pub mod cli_reader;
 ***********************************************************************************/
 
 use crate::err::AppError;
+use crate::scripts::ScriptFilter;
+use crate::langs::LangFilter;
+use crate::tables::GeoTables;
 use sqlx::postgres::{PgPoolOptions, PgConnectOptions, PgPool};
 use std::path::PathBuf;
 use cli_reader::{CliPars, Flags};
@@ -36,6 +39,10 @@ pub struct InitParams {
     pub log_folder: PathBuf,
     pub output_folder: PathBuf,
     pub source_file_name: String,
+    pub script_filter: ScriptFilter,
+    pub lang_filter: LangFilter,
+    pub postal_codes: bool,
+    pub tables: GeoTables,
     pub flags: Flags,
 }
 
@@ -95,6 +102,31 @@ pub fn get_params(cli_pars: CliPars, config_string: &String) -> Result<InitParam
         }
     }
 
+    // The scripts to be included. The -n flag includes all scripts, otherwise
+    // a list in the CL args takes precedence over one in the config file.
+    // If neither is given only Latin names are included.
+
+    let script_filter = if cli_pars.flags.include_nonlatin {
+        ScriptFilter::all()
+    }
+    else if !cli_pars.scripts.is_empty() {
+        ScriptFilter::from_names(&cli_pars.scripts)?
+    }
+    else if !config_file.import_pars.scripts.is_empty() {
+        ScriptFilter::from_names(&config_file.import_pars.scripts)?
+    }
+    else {
+        ScriptFilter::latin_only()
+    };
+
+    // The language codes to be included or excluded.
+
+    let lang_filter = LangFilter::new(&config_file.import_pars.include_langs, &config_file.import_pars.exclude_langs)?;
+
+    // The schema and table prefix used in all the SQL statements.
+
+    let tables = GeoTables::new(&config_file.db_pars.db_schema, &config_file.db_pars.table_prefix)?;
+
     // For execution flags read from the environment variables
     
     Ok(InitParams {
@@ -102,6 +134,10 @@ pub fn get_params(cli_pars: CliPars, config_string: &String) -> Result<InitParam
         log_folder,
         output_folder,
         source_file_name,
+        script_filter,
+        lang_filter,
+        postal_codes: config_file.import_pars.postal_codes,
+        tables,
         flags: cli_pars.flags,
     })
 
@@ -0,0 +1,284 @@
+use crate::AppError;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use csv::ReaderBuilder;
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use zip::{CompressionMethod, ZipArchive};
+
+// Opens the Geonames source files, which may be plain text, gzipped or in a
+// zip archive (as alternateNamesV2.zip is distributed), so that they can be
+// read without being extracted first. The format is taken from the file
+// extension (.zip or .gz), or otherwise from the first bytes of the file.
+
+#[derive(Debug, PartialEq)]
+enum SourceFormat {
+    Text,
+    Gzip,
+    Zip,
+}
+
+// The two column layouts of the alternate names files. The original (v1)
+// alternateNames.txt has 8 columns, and alternateNamesV2.txt adds the
+// 'from' and 'to' columns, giving 10.
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SourceLayout {
+    V1,
+    V2,
+}
+
+impl SourceLayout {
+    pub fn describe(&self) -> &str {
+        match self {
+            SourceLayout::V1 => "v1 (8 columns, without from / to)",
+            SourceLayout::V2 => "V2 (10 columns, with from / to)",
+        }
+    }
+}
+
+// The number of rows read from the start of a file to determine its layout.
+
+const LAYOUT_SAMPLE_SIZE: usize = 20;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZIP_MAGIC: [u8; 4] = [b'P', b'K', 0x03, 0x04];
+
+
+pub fn get_csv_reader(source_file_path: &Path) -> Result<csv::Reader<Box<dyn Read + Send>>, AppError> {
+    get_csv_reader_from(source_file_path, 0)
+}
+
+
+// As above, but starting at a byte offset in the source (in the unzipped
+// data, for a compressed file), which should be the start of a record.
+// Positions given by the reader are then relative to that offset.
+
+pub fn get_csv_reader_from(source_file_path: &Path, offset: u64) -> Result<csv::Reader<Box<dyn Read + Send>>, AppError> {
+
+    let reader = open_source_file(source_file_path, offset)?;
+    Ok(ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(9)
+        .from_reader(reader))
+}
+
+
+// The source data itself (unzipped, for a compressed file), from its start.
+
+pub fn get_data_reader(source_file_path: &Path) -> Result<Box<dyn Read + Send>, AppError> {
+    open_source_file(source_file_path, 0)
+}
+
+
+// Finds the layout of a file from the number of columns in its first rows,
+// which must all have the same number of columns. An empty file is taken
+// to be V2.
+
+pub fn detect_layout(source_file_path: &Path) -> Result<SourceLayout, AppError> {
+
+    let reader = open_source_file(source_file_path, 0)?;
+    let mut csv_rdr = ReaderBuilder::new()
+        .has_headers(false)
+        .delimiter(9)
+        .flexible(true)
+        .from_reader(reader);
+
+    let mut field_counts = Vec::with_capacity(LAYOUT_SAMPLE_SIZE);
+    let mut record = csv::StringRecord::new();
+    while field_counts.len() < LAYOUT_SAMPLE_SIZE && csv_rdr.read_record(&mut record)? {
+        field_counts.push(record.len());
+    }
+
+    get_layout(&field_counts)
+        .map_err(|e| AppError::SourceFileError(e, source_file_path.to_owned()))
+}
+
+
+fn get_layout(field_counts: &[usize]) -> Result<SourceLayout, String> {
+
+    let first = match field_counts.first() {
+        Some(n) => *n,
+        None => return Ok(SourceLayout::V2),
+    };
+    if let Some(n) = field_counts.iter().find(|n| **n != first) {
+        return Err(format!("The first rows have different numbers of columns ({} and {})", first, n));
+    }
+    match first {
+        8 => Ok(SourceLayout::V1),
+        10 => Ok(SourceLayout::V2),
+        n => Err(format!("Rows have {} columns, rather than the 8 (v1) or 10 (V2) expected", n)),
+    }
+}
+
+
+fn open_source_file(source_file_path: &Path, offset: u64) -> Result<Box<dyn Read + Send>, AppError> {
+
+    let io_error = |e: std::io::Error| AppError::IoReadErrorWithPath(e, source_file_path.to_owned());
+
+    let mut file = File::open(source_file_path).map_err(io_error)?;
+
+    let mut magic = [0u8; 4];
+    let magic_len = read_up_to(&mut file, &mut magic).map_err(io_error)?;
+    file.rewind().map_err(io_error)?;
+
+    // A plain text file can be positioned directly. Compressed data has to
+    // be decompressed up to the offset, and the data before it discarded.
+
+    let mut reader: Box<dyn Read + Send> = match get_source_format(source_file_path, &magic[..magic_len]) {
+        SourceFormat::Text => {
+            file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+            return Ok(Box::new(BufReader::new(file)));
+        },
+        SourceFormat::Gzip => Box::new(MultiGzDecoder::new(BufReader::new(file))),
+        SourceFormat::Zip => open_zip_entry(source_file_path, file)?,
+    };
+    if offset > 0 {
+        std::io::copy(&mut reader.by_ref().take(offset), &mut std::io::sink()).map_err(io_error)?;
+    }
+    Ok(reader)
+}
+
+
+// The date and time at which the source data was last modified by Geonames.
+// For a zip archive this is the time recorded for the entry read, which is
+// kept when the archive is downloaded. Otherwise it is the file's own
+// modification time (which a download may or may not preserve), in UTC.
+
+pub fn get_source_modified(source_file_path: &Path) -> Result<Option<NaiveDateTime>, AppError> {
+
+    let io_error = |e: std::io::Error| AppError::IoReadErrorWithPath(e, source_file_path.to_owned());
+
+    let mut file = File::open(source_file_path).map_err(io_error)?;
+    let mut magic = [0u8; 4];
+    let magic_len = read_up_to(&mut file, &mut magic).map_err(io_error)?;
+    file.rewind().map_err(io_error)?;
+
+    if get_source_format(source_file_path, &magic[..magic_len]) == SourceFormat::Zip {
+        let zip_error = |e: zip::result::ZipError| AppError::SourceFileError(e.to_string(), source_file_path.to_owned());
+        let mut archive = ZipArchive::new(BufReader::new(file)).map_err(zip_error)?;
+        if let Some(entry_name) = get_zip_entry_name(source_file_path, archive.file_names()) {
+            let index = archive.index_for_name(&entry_name).unwrap_or_default();
+            let entry = archive.by_index_raw(index).map_err(zip_error)?;
+            return Ok(entry.last_modified().and_then(|dt| {
+                NaiveDate::from_ymd_opt(dt.year() as i32, dt.month() as u32, dt.day() as u32)?
+                    .and_hms_opt(dt.hour() as u32, dt.minute() as u32, dt.second() as u32)
+            }));
+        }
+        return Ok(None);
+    }
+
+    let modified = file.metadata().and_then(|m| m.modified()).map_err(io_error)?;
+    Ok(Some(DateTime::<Utc>::from(modified).naive_utc()))
+}
+
+
+fn get_source_format(source_file_path: &Path, magic: &[u8]) -> SourceFormat {
+
+    let extension = source_file_path.extension()
+                .map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+    match extension.as_str() {
+        "zip" => SourceFormat::Zip,
+        "gz" => SourceFormat::Gzip,
+        _ if magic.starts_with(&ZIP_MAGIC) => SourceFormat::Zip,
+        _ if magic.starts_with(&GZIP_MAGIC) => SourceFormat::Gzip,
+        _ => SourceFormat::Text,
+    }
+}
+
+
+// The entry read from a zip archive is the one with the archive's name and
+// a .txt extension (alternateNamesV2.txt in alternateNamesV2.zip), or if
+// there is no such entry the first whose name begins with 'alternateNames'.
+// The entry's data is read directly from the file and decompressed as it is
+// read, so that the reader does not borrow from the archive.
+
+fn open_zip_entry(source_file_path: &Path, file: File) -> Result<Box<dyn Read + Send>, AppError> {
+
+    let zip_error = |e: zip::result::ZipError| AppError::SourceFileError(e.to_string(), source_file_path.to_owned());
+
+    let mut archive = ZipArchive::new(BufReader::new(file)).map_err(zip_error)?;
+    let entry_name = get_zip_entry_name(source_file_path, archive.file_names())
+                .ok_or_else(|| AppError::SourceFileError(format!("No alternate names entry found in the archive (entries are: {})",
+                            archive.file_names().collect::<Vec<&str>>().join(", ")), source_file_path.to_owned()))?;
+    let index = archive.index_for_name(&entry_name).unwrap_or_default();
+
+    let (compression, data_start, compressed_size) = {
+        let entry = archive.by_index_raw(index).map_err(zip_error)?;
+        (entry.compression(), entry.data_start(), entry.compressed_size())
+    };
+
+    let mut reader = archive.into_inner();
+    reader.seek(SeekFrom::Start(data_start))
+        .map_err(|e| AppError::IoReadErrorWithPath(e, source_file_path.to_owned()))?;
+    let data = reader.take(compressed_size);
+
+    match compression {
+        CompressionMethod::STORE => Ok(Box::new(data)),
+        CompressionMethod::DEFLATE => Ok(Box::new(DeflateDecoder::new(data))),
+        other => Err(AppError::SourceFileError(format!("Entry {} uses an unsupported compression method ({})", entry_name, other),
+                    source_file_path.to_owned())),
+    }
+}
+
+
+fn get_zip_entry_name<'a>(source_file_path: &Path, entry_names: impl Iterator<Item = &'a str>) -> Option<String> {
+
+    let stem = source_file_path.file_stem()?.to_string_lossy();
+    let expected = format!("{}.txt", stem);
+    let names: Vec<&str> = entry_names.collect();
+
+    names.iter().find(|n| **n == expected)
+        .or_else(|| names.iter().find(|n| n.starts_with("alternateNames")))
+        .map(|n| n.to_string())
+}
+
+
+fn read_up_to(file: &mut File, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match file.read(&mut buf[total..])? {
+            0 => break,
+            n => total += n,
+        }
+    }
+    Ok(total)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_format_from_extension() {
+        assert_eq!(get_source_format(Path::new("alternateNamesV2.zip"), b""), SourceFormat::Zip);
+        assert_eq!(get_source_format(Path::new("alternateNamesV2.txt.GZ"), b""), SourceFormat::Gzip);
+        assert_eq!(get_source_format(Path::new("alternateNamesV2.txt"), b"1\t26"), SourceFormat::Text);
+    }
+
+    #[test]
+    fn check_format_from_magic_bytes() {
+        assert_eq!(get_source_format(Path::new("alternateNamesV2"), &ZIP_MAGIC), SourceFormat::Zip);
+        assert_eq!(get_source_format(Path::new("alternateNamesV2.txt"), &[0x1f, 0x8b, 0x08, 0x00]), SourceFormat::Gzip);
+        assert_eq!(get_source_format(Path::new("alternateNamesV2.txt"), b""), SourceFormat::Text);
+    }
+
+    #[test]
+    fn check_layout_from_column_counts() {
+        assert_eq!(get_layout(&[10, 10, 10]), Ok(SourceLayout::V2));
+        assert_eq!(get_layout(&[8, 8]), Ok(SourceLayout::V1));
+        assert_eq!(get_layout(&[]), Ok(SourceLayout::V2));
+        assert!(get_layout(&[8, 10]).is_err());
+        assert!(get_layout(&[9]).is_err());
+    }
+
+    #[test]
+    fn check_zip_entry_chosen_by_name() {
+        let entries = ["iso-languagecodes.txt", "alternateNamesV2.txt"];
+        assert_eq!(get_zip_entry_name(Path::new("alternateNamesV2.zip"), entries.into_iter()), Some("alternateNamesV2.txt".to_string()));
+        assert_eq!(get_zip_entry_name(Path::new("download.zip"), entries.into_iter()), Some("alternateNamesV2.txt".to_string()));
+        assert_eq!(get_zip_entry_name(Path::new("download.zip"), ["readme.txt"].into_iter()), None);
+    }
+}
@@ -0,0 +1,107 @@
+/***************************************************************************
+ * The names of the schema and tables used by the program. The schema name
+ * and a table name prefix are set in the [database] section of the config
+ * file (db_schema and table_prefix), so that several Geonames releases, or
+ * test copies, can be held in the same database side by side. Every SQL
+ * statement takes its table names from here.
+ ***************************************************************************/
+
+use crate::err::AppError;
+
+pub const DEFAULT_SCHEMA: &str = "geo";
+
+// Names are included in SQL statements as they are, so are restricted to
+// lower case letters, digits and underscores, not starting with a digit,
+// and are kept short enough for the derived schema and index names to stay
+// within Postgres's 63 character limit.
+
+const MAX_SCHEMA_LENGTH: usize = 40;
+const MAX_PREFIX_LENGTH: usize = 24;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoTables {
+    pub schema: String,
+    pub prefix: String,
+}
+
+impl GeoTables {
+    pub fn new(schema: &str, prefix: &str) -> Result<Self, AppError> {
+
+        let schema = schema.trim().to_string();
+        let prefix = prefix.trim().to_string();
+        if !is_valid_name(&schema, MAX_SCHEMA_LENGTH) {
+            return Err(AppError::ConfigurationError("Invalid schema name.".to_string(),
+                format!("'{}' should be lower case letters, digits and underscores, not starting with a digit, and at most {} characters.",
+                        schema, MAX_SCHEMA_LENGTH)));
+        }
+        if !prefix.is_empty() && !is_valid_name(&prefix, MAX_PREFIX_LENGTH) {
+            return Err(AppError::ConfigurationError("Invalid table prefix.".to_string(),
+                format!("'{}' should be lower case letters, digits and underscores, not starting with a digit, and at most {} characters.",
+                        prefix, MAX_PREFIX_LENGTH)));
+        }
+        Ok(GeoTables { schema, prefix })
+    }
+
+    // The schemas in which a full import is built, and in which the tables
+    // it replaces are kept, named after the live schema.
+
+    pub fn staging(&self) -> Self {
+        GeoTables { schema: format!("{}_staging", self.schema), prefix: self.prefix.clone() }
+    }
+
+    pub fn backup(&self) -> Self {
+        GeoTables { schema: format!("{}_backup", self.schema), prefix: self.prefix.clone() }
+    }
+
+    // The schema qualified name of a table, e.g. "geo.alt_names".
+
+    pub fn name(&self, table: &str) -> String {
+        format!("{}.{}{}", self.schema, self.prefix, table)
+    }
+
+    // The unqualified name of a table or index, with the prefix added.
+
+    pub fn local_name(&self, name: &str) -> String {
+        format!("{}{}", self.prefix, name)
+    }
+}
+
+impl Default for GeoTables {
+    fn default() -> Self {
+        GeoTables { schema: DEFAULT_SCHEMA.to_string(), prefix: String::new() }
+    }
+}
+
+
+fn is_valid_name(name: &str, max_length: usize) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_lowercase() || c == '_' => (),
+        _ => return false,
+    }
+    name.len() <= max_length && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn check_names_with_prefix() {
+        let tables = GeoTables::new("geonames", "v2025_").unwrap();
+        assert_eq!(tables.name("alt_names"), "geonames.v2025_alt_names");
+        assert_eq!(tables.staging().name("alt_names"), "geonames_staging.v2025_alt_names");
+        assert_eq!(tables.backup().local_name("codes_idx"), "v2025_codes_idx");
+        assert_eq!(GeoTables::default().name("codes"), "geo.codes");
+    }
+
+    #[test]
+    fn check_invalid_names_rejected() {
+        assert!(GeoTables::new("Geo", "").is_err());
+        assert!(GeoTables::new("geo; drop table x", "").is_err());
+        assert!(GeoTables::new("", "").is_err());
+        assert!(GeoTables::new("geo", "2025_").is_err());
+        assert!(GeoTables::new("geo", "test_").is_ok());
+    }
+}